@@ -0,0 +1,536 @@
+#[cfg(feature = "ble")]
+pub mod ble;
+pub mod events;
+pub mod fifo;
+#[cfg(feature = "idle-inhibit")]
+pub mod idle_inhibit;
+pub mod light;
+pub mod meeting;
+#[cfg(feature = "notify")]
+pub mod notify;
+#[cfg(feature = "obs")]
+pub mod obs;
+pub mod routing;
+pub mod script;
+#[cfg(feature = "serial")]
+pub mod serial;
+pub mod slack;
+pub mod webhook;
+#[cfg(feature = "x11")]
+pub mod x11;
+
+use crate::config::{Config, TallyMode};
+#[cfg(not(feature = "notify"))]
+use crate::recording_watcher::DebugActor;
+use crate::recording_watcher::{FanOutAlarm, HookError, OnAirActor, Transition};
+use snafu::prelude::*;
+use std::process::{Command, ExitStatus};
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Used for commands if no `command_timeout` is configured.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(context(false), display("{}", source))]
+    Webhook { source: webhook::Error },
+    #[snafu(context(false), display("{}", source))]
+    Fifo { source: fifo::Error },
+    #[snafu(context(false), display("{}", source))]
+    Light { source: light::Error },
+}
+
+/// Builds all actors configured, routed per device if there are `routes`.
+pub fn from_config(config: &Config) -> Result<Box<dyn OnAirActor>, Error> {
+    let actor = top_level_actor(config)?;
+    if config.routes.is_empty() {
+        if config.tally == TallyMode::PerDevice {
+            warn!("tally = \"per_device\" has no effect without routes");
+        }
+        return Ok(actor);
+    }
+    let per_device = config.tally == TallyMode::PerDevice;
+    let routing =
+        routing::RoutingActor::new(&config.routes, actor, config.command_timeout, per_device)?;
+    Ok(Box::new(routing))
+}
+
+/// The actors configured at the top level, falling back to desktop notifications if there are
+/// none.
+fn top_level_actor(config: &Config) -> Result<Box<dyn OnAirActor>, Error> {
+    let mut actors: Vec<(&str, Box<dyn OnAirActor>)> = Vec::new();
+    if config.on_air_cmd.is_some()
+        || config.off_air_cmd.is_some()
+        || config.warning_cmd.is_some()
+        || config.fan_out_cmd.is_some()
+    {
+        let actor = ShellCommandActor::new(config.on_air_cmd.clone(), config.off_air_cmd.clone())
+            .with_warning_cmd(config.warning_cmd.clone())
+            .with_fan_out_cmd(config.fan_out_cmd.clone())
+            .with_timeout(config.command_timeout);
+        actors.push(("shell", Box::new(actor)));
+    }
+    if config.on_air_exec.is_some() || config.off_air_exec.is_some() {
+        let actor = ExecActor::new(config.on_air_exec.clone(), config.off_air_exec.clone())
+            .with_timeout(config.command_timeout);
+        actors.push(("exec", Box::new(actor)));
+    }
+    if config.on_air_script.is_some() || config.off_air_script.is_some() {
+        let actor = script::ScriptActor::new(
+            config.on_air_script.clone(),
+            config.off_air_script.clone(),
+            config.command_timeout,
+        );
+        actors.push(("script", Box::new(actor)));
+    }
+    if let Some(webhook) = &config.webhook {
+        actors.push(("webhook", Box::new(webhook::WebhookActor::new(webhook)?)));
+    }
+    if let Some(light) = &config.light {
+        actors.push(("light", Box::new(light::LightActor::new(light)?)));
+    }
+    if let Some(meeting_mode) = &config.meeting_mode {
+        let actor = meeting::preset(meeting_mode, config.command_timeout)?;
+        actors.push(("meeting mode", Box::new(actor)));
+    }
+    #[cfg(feature = "obs")]
+    if let Some(obs) = &config.obs {
+        actors.push(("obs", Box::new(obs::ObsActor::new(obs))));
+    }
+    #[cfg(feature = "serial")]
+    if let Some(serial) = &config.serial {
+        actors.push(("serial", Box::new(serial::SerialActor::new(serial))));
+    }
+    #[cfg(feature = "ble")]
+    if let Some(ble) = &config.ble {
+        actors.push(("ble", Box::new(ble::BleActor::new(ble))));
+    }
+    #[cfg(feature = "idle-inhibit")]
+    if config.idle_inhibit {
+        match idle_inhibit::IdleInhibitActor::new() {
+            Ok(actor) => actors.push(("idle inhibit", Box::new(actor))),
+            Err(e) => tracing::warn!("no session bus, disabling idle inhibition: {}", e),
+        }
+    }
+    #[cfg(feature = "x11")]
+    if config.x11_property {
+        match x11::X11PropertyActor::new() {
+            Ok(actor) => actors.push(("x11 property", Box::new(actor))),
+            Err(e) => warn!("X11 is not available, not publishing {}: {}", x11::PROPERTY, e),
+        }
+    }
+    if config.emit_events {
+        actors.push(("event stream", Box::new(events::EventStreamActor)));
+    }
+    if let Some(path) = &config.fifo {
+        actors.push(("fifo", Box::new(fifo::FifoActor::spawn(path)?)));
+    }
+    for name in config.actor_priority.keys() {
+        if !actors.iter().any(|(actor, _)| priority_key(actor) == *name) {
+            warn!(
+                "actor_priority sets [{}], which is not an enabled actor",
+                name
+            );
+        }
+    }
+    let priority = |name: &str| {
+        config
+            .actor_priority
+            .get(&priority_key(name))
+            .copied()
+            .unwrap_or(DEFAULT_ACTOR_PRIORITY)
+    };
+    if actors.is_empty() {
+        info!("No actors configured, showing desktop notifications");
+    } else {
+        let mut names: Vec<&str> = actors.iter().map(|(name, _)| *name).collect();
+        names.sort_by_key(|name| priority(name));
+        info!("Enabled actors: {}", names.join(", "));
+    }
+    let mut actors: Vec<(i32, Box<dyn OnAirActor>)> = actors
+        .into_iter()
+        .map(|(name, actor)| (priority(name), actor))
+        .collect();
+    Ok(match actors.len() {
+        #[cfg(feature = "notify")]
+        0 => Box::new(notify::NotifyActor::new(
+            config.notification.clone(),
+            &config.scopes,
+        )),
+        #[cfg(not(feature = "notify"))]
+        0 => Box::new(DebugActor::new(config.notification.clone(), &config.scopes)),
+        1 => actors.remove(0).1,
+        _ => Box::new(CompositeActor::with_priorities(actors)),
+    })
+}
+
+/// Name of an actor in `actor_priority`.
+fn priority_key(name: &str) -> String {
+    name.replace(' ', "_")
+}
+
+/// Forwards to an actor that can be replaced at runtime. Actors are invoked from the PipeWire
+/// loop while replacements come from other threads, which is why all actors have to be `Send`
+/// and `Sync`. A replacement takes effect with the next transition.
+pub struct SwappableActor {
+    inner: RwLock<Box<dyn OnAirActor>>,
+}
+
+impl SwappableActor {
+    pub fn new(actor: Box<dyn OnAirActor>) -> Self {
+        SwappableActor {
+            inner: RwLock::new(actor),
+        }
+    }
+
+    /// Blocks until a hook that is running on the current actor returned, which can take up to
+    /// `command_timeout`.
+    pub fn replace(&self, actor: Box<dyn OnAirActor>) {
+        *self.inner.write().unwrap() = actor;
+    }
+}
+
+impl OnAirActor for SwappableActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.inner.read().unwrap().go_on_air(transition)
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.inner.read().unwrap().go_off_air(transition)
+    }
+
+    fn on_level_change(&self, count: usize) -> Result<(), HookError> {
+        self.inner.read().unwrap().on_level_change(count)
+    }
+
+    fn go_warning(&self, transition: &Transition, on_air_for: Duration) -> Result<(), HookError> {
+        self.inner
+            .read()
+            .unwrap()
+            .go_warning(transition, on_air_for)
+    }
+
+    fn on_devices_change(&self, transition: &Transition) -> Result<(), HookError> {
+        self.inner.read().unwrap().on_devices_change(transition)
+    }
+
+    fn go_fan_out_alarm(&self, alarm: &FanOutAlarm) -> Result<(), HookError> {
+        self.inner.read().unwrap().go_fan_out_alarm(alarm)
+    }
+}
+
+/// Runs the configured commands through `sh -c` on transitions. Labels, devices and apps are
+/// passed as `ONAIR_LABEL`, `ONAIR_DEVICES` and `ONAIR_APPS` in the environment rather than
+/// substituted into the command, so names can't inject shell code.
+pub struct ShellCommandActor {
+    on_air_cmd: Option<String>,
+    off_air_cmd: Option<String>,
+    warning_cmd: Option<String>,
+    fan_out_cmd: Option<String>,
+    timeout: Duration,
+}
+
+impl ShellCommandActor {
+    pub fn new(on_air_cmd: Option<String>, off_air_cmd: Option<String>) -> Self {
+        ShellCommandActor {
+            on_air_cmd,
+            off_air_cmd,
+            warning_cmd: None,
+            fan_out_cmd: None,
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+        }
+    }
+
+    /// Also run `warning_cmd` when having been on air for too long.
+    pub fn with_warning_cmd(mut self, warning_cmd: Option<String>) -> Self {
+        self.warning_cmd = warning_cmd;
+        self
+    }
+
+    /// Also run `fan_out_cmd` when a device has an unexpected number of links.
+    pub fn with_fan_out_cmd(mut self, fan_out_cmd: Option<String>) -> Self {
+        self.fan_out_cmd = fan_out_cmd;
+        self
+    }
+
+    /// Kill commands still running after `timeout`, zero waits forever.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn run(&self, command: &Option<String>, transition: &Transition) -> Result<(), HookError> {
+        self.run_with(command, &transition.label(), &transition.devices, &transition.apps)
+    }
+
+    fn run_with(
+        &self,
+        command: &Option<String>,
+        label: &str,
+        devices: &[String],
+        apps: &[String],
+    ) -> Result<(), HookError> {
+        let Some(command) = command else {
+            return Ok(());
+        };
+        info!("running [{}]", command);
+        let mut shell = Command::new("sh");
+        shell
+            .arg("-c")
+            .arg(command)
+            .env("ONAIR_LABEL", label)
+            .env("ONAIR_DEVICES", devices.join(", "))
+            .env("ONAIR_APPS", apps.join(", "));
+        let status = run_with_timeout(&mut shell, self.timeout)?;
+        if !status.success() {
+            return Err(format!("command [{}] exited with [{}]", command, status).into());
+        }
+        Ok(())
+    }
+}
+
+impl OnAirActor for ShellCommandActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run(&self.on_air_cmd, transition)
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run(&self.off_air_cmd, transition)
+    }
+
+    fn go_warning(&self, transition: &Transition, _on_air_for: Duration) -> Result<(), HookError> {
+        self.run(&self.warning_cmd, transition)
+    }
+
+    fn go_fan_out_alarm(&self, alarm: &FanOutAlarm) -> Result<(), HookError> {
+        let devices = [alarm.device.clone()];
+        self.run_with(&self.fan_out_cmd, &alarm.label, &devices, &alarm.apps)
+    }
+}
+
+/// Runs the configured programs directly with their arguments on transitions, without a
+/// shell in between. `{device}`, `{app}` and `{state}` are replaced within each argument, so
+/// device and app names can't break the command however they are quoted.
+pub struct ExecActor {
+    on_air_exec: Option<Vec<String>>,
+    off_air_exec: Option<Vec<String>>,
+    timeout: Duration,
+}
+
+impl ExecActor {
+    pub fn new(on_air_exec: Option<Vec<String>>, off_air_exec: Option<Vec<String>>) -> Self {
+        ExecActor {
+            on_air_exec,
+            off_air_exec,
+            timeout: DEFAULT_COMMAND_TIMEOUT,
+        }
+    }
+
+    /// Kill programs still running after `timeout`, zero waits forever.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn run(
+        &self,
+        command: &Option<Vec<String>>,
+        state: &str,
+        transition: &Transition,
+    ) -> Result<(), HookError> {
+        let Some((program, args)) = command.as_ref().and_then(|command| command.split_first())
+        else {
+            return Ok(());
+        };
+        let devices = transition.devices.join(", ");
+        let apps = transition.apps.join(", ");
+        let args: Vec<String> = args
+            .iter()
+            .map(|arg| {
+                arg.replace("{device}", &devices)
+                    .replace("{app}", &apps)
+                    .replace("{state}", state)
+            })
+            .collect();
+        info!("running [{}] with arguments {:?}", program, args);
+        let status = run_with_timeout(Command::new(program).args(&args), self.timeout)?;
+        if !status.success() {
+            return Err(format!("command [{}] exited with [{}]", program, status).into());
+        }
+        Ok(())
+    }
+}
+
+impl OnAirActor for ExecActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run(&self.on_air_exec, "on", transition)
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run(&self.off_air_exec, "off", transition)
+    }
+}
+
+/// Runs the command and waits for it to exit, killing it once it ran for longer than the
+/// timeout, so a hanging hook can't block the PipeWire loop forever.
+pub fn run_with_timeout(command: &mut Command, timeout: Duration) -> Result<ExitStatus, HookError> {
+    let mut child = command.spawn()?;
+    if timeout.is_zero() {
+        return Ok(child.wait()?);
+    }
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if started.elapsed() >= timeout {
+            warn!("command is still running after [{:?}], killing it", timeout);
+            child.kill()?;
+            child.wait()?;
+            return Err(format!("command timed out after [{:?}]", timeout).into());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Used for actors without an entry in `actor_priority`.
+pub const DEFAULT_ACTOR_PRIORITY: i32 = 0;
+
+/// Forwards every transition to all contained actors, in order.
+pub struct CompositeActor {
+    actors: Vec<Box<dyn OnAirActor>>,
+}
+
+impl CompositeActor {
+    pub fn new(actors: Vec<Box<dyn OnAirActor>>) -> Self {
+        CompositeActor { actors }
+    }
+
+    /// Invokes lower priorities first, actors with the same priority in the given order.
+    pub fn with_priorities(mut actors: Vec<(i32, Box<dyn OnAirActor>)>) -> Self {
+        actors.sort_by_key(|(priority, _)| *priority);
+        CompositeActor {
+            actors: actors.into_iter().map(|(_, actor)| actor).collect(),
+        }
+    }
+}
+
+impl CompositeActor {
+    /// Invokes every actor, even if earlier ones fail, and reports all failures together.
+    fn run_all(
+        &self,
+        hook: impl Fn(&dyn OnAirActor) -> Result<(), HookError>,
+    ) -> Result<(), HookError> {
+        let failures: Vec<String> = self
+            .actors
+            .iter()
+            .filter_map(|actor| hook(actor.as_ref()).err())
+            .map(|e| e.to_string())
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("; ").into())
+        }
+    }
+}
+
+impl OnAirActor for CompositeActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run_all(|actor| actor.go_on_air(transition))
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run_all(|actor| actor.go_off_air(transition))
+    }
+
+    fn on_level_change(&self, count: usize) -> Result<(), HookError> {
+        self.run_all(|actor| actor.on_level_change(count))
+    }
+
+    fn go_warning(&self, transition: &Transition, on_air_for: Duration) -> Result<(), HookError> {
+        self.run_all(|actor| actor.go_warning(transition, on_air_for))
+    }
+
+    fn on_devices_change(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run_all(|actor| actor.on_devices_change(transition))
+    }
+
+    fn go_fan_out_alarm(&self, alarm: &FanOutAlarm) -> Result<(), HookError> {
+        self.run_all(|actor| actor.go_fan_out_alarm(alarm))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    fn transition(devices: &[&str]) -> Transition {
+        let devices: Vec<String> = devices.iter().map(|device| device.to_string()).collect();
+        Transition {
+            at: SystemTime::now(),
+            labels: devices.clone(),
+            devices,
+            apps: vec!["App".to_string()],
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Appends its name to a shared log on every hook.
+    struct Named(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl OnAirActor for Named {
+        fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+            self.1.lock().unwrap().push(self.0);
+            Ok(())
+        }
+
+        fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+            self.1.lock().unwrap().push(self.0);
+            Err(format!("{} failed", self.0).into())
+        }
+    }
+
+    #[test]
+    fn composite_invokes_actors_by_priority() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let actor = |name| -> Box<dyn OnAirActor> { Box::new(Named(name, log.clone())) };
+        let composite = CompositeActor::with_priorities(vec![
+            (DEFAULT_ACTOR_PRIORITY, actor("shell")),
+            (DEFAULT_ACTOR_PRIORITY + 10, actor("webhook")),
+            (DEFAULT_ACTOR_PRIORITY, actor("exec")),
+            (DEFAULT_ACTOR_PRIORITY - 10, actor("serial")),
+        ]);
+        composite.go_on_air(&transition(&["Mic"])).unwrap();
+        assert_eq!(*log.lock().unwrap(), ["serial", "shell", "exec", "webhook"]);
+
+        // failures don't stop the later actors and are reported together
+        log.lock().unwrap().clear();
+        let error = composite.go_off_air(&transition(&[])).unwrap_err();
+        assert_eq!(*log.lock().unwrap(), ["serial", "shell", "exec", "webhook"]);
+        assert_eq!(
+            error.to_string(),
+            "serial failed; shell failed; exec failed; webhook failed"
+        );
+    }
+
+    #[test]
+    fn shell_commands_get_names_through_the_environment() {
+        let dir = std::env::temp_dir().join(format!("onair-buddy-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("label");
+        let command = format!("printf %s \"$ONAIR_LABEL\" > '{}'", output.display());
+        let actor = ShellCommandActor::new(Some(command), None);
+        let device = "$(touch injected) %label%";
+        actor.go_on_air(&transition(&[device])).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), device);
+        assert!(!dir.join("injected").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use btleplug::api::{
+    Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType,
+};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use serde::Deserialize;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How long to scan for the peripheral before giving up until the next transition.
+const SCAN_TIME: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BleConfig {
+    /// Advertised name of the peripheral.
+    pub name: Option<String>,
+    /// UUID of a service the peripheral advertises, for signs without a unique name.
+    pub service: Option<String>,
+    /// UUID of the characteristic written on transitions.
+    pub characteristic: String,
+    #[serde(default = "default_on_air")]
+    pub on_air: Vec<u8>,
+    #[serde(default = "default_off_air")]
+    pub off_air: Vec<u8>,
+    /// Write without waiting for the peripheral to acknowledge, for characteristics that
+    /// only support that.
+    #[serde(default)]
+    pub without_response: bool,
+}
+
+fn default_on_air() -> Vec<u8> {
+    vec![1]
+}
+
+fn default_off_air() -> Vec<u8> {
+    vec![0]
+}
+
+/// Writes the configured bytes to a characteristic of a Bluetooth LE peripheral on
+/// transitions, e.g. for a battery powered sign.
+///
+/// btleplug is async, so writes run on a dedicated thread with its own runtime and never
+/// block the PipeWire loop. The connection is kept between transitions; if the sign went out
+/// of range or was switched off, it is looked for again on the next transition. Failures are
+/// only logged there.
+pub struct BleActor {
+    transitions: Sender<bool>,
+}
+
+impl BleActor {
+    pub fn new(config: &BleConfig) -> Self {
+        if config.name.is_none() && config.service.is_none() {
+            warn!("[ble] has neither name nor service set, using the first peripheral found");
+        }
+        let (transitions, receiver) = mpsc::channel();
+        let config = config.clone();
+        thread::spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    warn!("failed to start runtime for BLE actor: {}", e);
+                    return;
+                }
+            };
+            let mut connection = BleConnection {
+                config,
+                peripheral: None,
+            };
+            for on_air in receiver {
+                runtime.block_on(connection.apply(on_air));
+            }
+        });
+        BleActor { transitions }
+    }
+
+    fn send(&self, on_air: bool) -> Result<(), HookError> {
+        self.transitions
+            .send(on_air)
+            .map_err(|_| "BLE actor is no longer running".into())
+    }
+}
+
+impl OnAirActor for BleActor {
+    fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.send(true)
+    }
+
+    fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.send(false)
+    }
+}
+
+struct BleConnection {
+    config: BleConfig,
+    peripheral: Option<(Peripheral, Characteristic)>,
+}
+
+impl BleConnection {
+    /// Retries once with a fresh connection, as the peripheral may have been out of range or
+    /// restarted since the last transition.
+    async fn apply(&mut self, on_air: bool) {
+        let data = if on_air {
+            &self.config.on_air
+        } else {
+            &self.config.off_air
+        };
+        let write_type = if self.config.without_response {
+            WriteType::WithoutResponse
+        } else {
+            WriteType::WithResponse
+        };
+        for attempt in 1..=2 {
+            let (peripheral, characteristic) = match self.peripheral.take() {
+                Some(connected) => connected,
+                None => match self.connect().await {
+                    Ok(connected) => connected,
+                    Err(e) => {
+                        warn!("BLE sign is not reachable: {}", e);
+                        return;
+                    }
+                },
+            };
+            match peripheral.write(&characteristic, data, write_type).await {
+                Ok(()) => {
+                    self.peripheral = Some((peripheral, characteristic));
+                    return;
+                }
+                Err(e) if attempt == 1 => debug!("BLE write failed, reconnecting: {}", e),
+                Err(e) => warn!("BLE write failed: {}", e),
+            }
+            let _ = peripheral.disconnect().await;
+        }
+    }
+
+    async fn connect(&self) -> Result<(Peripheral, Characteristic), HookError> {
+        let manager = Manager::new().await?;
+        let adapter = manager
+            .adapters()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or("no Bluetooth adapter found")?;
+        let peripheral = self.find(&adapter).await?;
+        if !peripheral.is_connected().await? {
+            peripheral.connect().await?;
+        }
+        peripheral.discover_services().await?;
+        let characteristic = peripheral
+            .characteristics()
+            .into_iter()
+            .find(|characteristic| {
+                uuid_matches(
+                    &characteristic.uuid.to_string(),
+                    &self.config.characteristic,
+                )
+            })
+            .ok_or_else(|| {
+                format!(
+                    "peripheral has no characteristic [{}]",
+                    self.config.characteristic
+                )
+            })?;
+        info!("Connected to BLE sign [{}]", peripheral.address());
+        Ok((peripheral, characteristic))
+    }
+
+    /// Scans for the first peripheral matching the configured name and service.
+    async fn find(&self, adapter: &Adapter) -> Result<Peripheral, HookError> {
+        debug!("scanning for BLE sign for up to [{:?}]", SCAN_TIME);
+        adapter.start_scan(ScanFilter::default()).await?;
+        let mut found = None;
+        let started = tokio::time::Instant::now();
+        while found.is_none() && started.elapsed() < SCAN_TIME {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            for peripheral in adapter.peripherals().await? {
+                let Some(properties) = peripheral.properties().await? else {
+                    continue;
+                };
+                let name_matches = self
+                    .config
+                    .name
+                    .as_ref()
+                    .is_none_or(|name| properties.local_name.as_ref() == Some(name));
+                let service_matches = self.config.service.as_ref().is_none_or(|service| {
+                    properties
+                        .services
+                        .iter()
+                        .any(|uuid| uuid_matches(&uuid.to_string(), service))
+                });
+                if name_matches && service_matches {
+                    found = Some(peripheral);
+                    break;
+                }
+            }
+        }
+        let _ = adapter.stop_scan().await;
+        found.ok_or_else(|| {
+            format!(
+                "no peripheral with name [{}] and service [{}] found",
+                self.config.name.as_deref().unwrap_or("any"),
+                self.config.service.as_deref().unwrap_or("any")
+            )
+            .into()
+        })
+    }
+}
+
+fn uuid_matches(uuid: &str, configured: &str) -> bool {
+    uuid.eq_ignore_ascii_case(configured.trim())
+}
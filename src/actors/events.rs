@@ -0,0 +1,38 @@
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use std::io::{self, Write};
+use std::time::UNIX_EPOCH;
+
+/// Prints one line per transition to stdout, e.g. `ON 1699999999 "My Mic"` or
+/// `OFF 1699999999`, for piping into other tools.
+pub struct EventStreamActor;
+
+impl EventStreamActor {
+    fn emit(line: &str) -> Result<(), HookError> {
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn timestamp(transition: &Transition) -> u64 {
+        transition
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+impl OnAirActor for EventStreamActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        let mut line = format!("ON {}", Self::timestamp(transition));
+        for device in &transition.devices {
+            line.push_str(&format!(" {:?}", device));
+        }
+        Self::emit(&line)
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        Self::emit(&format!("OFF {}", Self::timestamp(transition)))
+    }
+}
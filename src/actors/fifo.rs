@@ -0,0 +1,103 @@
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use snafu::prelude::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("[{}] exists but is not a FIFO", path.display()))]
+    NotAFifo { path: PathBuf },
+    #[snafu(display("Failed to run mkfifo for [{}]", path.display()))]
+    RunMkfifo {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("mkfifo failed for [{}] with {}", path.display(), status))]
+    Mkfifo { path: PathBuf, status: ExitStatus },
+}
+
+/// Writes `on` or `off` lines to a named pipe on transitions.
+///
+/// Opening a FIFO for writing blocks until a reader shows up, so writes happen on a
+/// background thread. Transitions that happen while nobody is reading are collapsed into
+/// the latest one, which is written as soon as a reader opens the FIFO.
+pub struct FifoActor {
+    lines: Sender<&'static str>,
+}
+
+impl FifoActor {
+    /// Creates the FIFO if it doesn't exist yet.
+    pub fn spawn(path: &Path) -> Result<Self, Error> {
+        match fs::metadata(path) {
+            Ok(metadata) => ensure!(metadata.file_type().is_fifo(), NotAFifoSnafu { path }),
+            Err(_) => {
+                info!("Creating FIFO [{}]", path.display());
+                let status = Command::new("mkfifo")
+                    .arg("-m")
+                    .arg("600")
+                    .arg(path)
+                    .status()
+                    .context(RunMkfifoSnafu { path })?;
+                ensure!(status.success(), MkfifoSnafu { path, status });
+            }
+        }
+        let (lines, receiver) = mpsc::channel();
+        let path = path.to_path_buf();
+        thread::spawn(move || write_lines(&path, receiver));
+        Ok(FifoActor { lines })
+    }
+
+    fn send(&self, line: &'static str) -> Result<(), HookError> {
+        self.lines
+            .send(line)
+            .map_err(|_| "FIFO writer is no longer running".into())
+    }
+}
+
+impl OnAirActor for FifoActor {
+    fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.send("on")
+    }
+
+    fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.send("off")
+    }
+}
+
+fn write_lines(path: &Path, receiver: Receiver<&'static str>) {
+    let mut fifo: Option<File> = None;
+    while let Ok(mut line) = receiver.recv() {
+        loop {
+            let file = match &mut fifo {
+                Some(file) => file,
+                // blocks until there is a reader
+                None => match OpenOptions::new().write(true).open(path) {
+                    Ok(file) => {
+                        // only the latest of the transitions that happened while waiting matters
+                        while let Ok(newer) = receiver.try_recv() {
+                            line = newer;
+                        }
+                        fifo.insert(file)
+                    }
+                    Err(e) => {
+                        warn!("failed to open FIFO [{}]: {}", path.display(), e);
+                        break;
+                    }
+                },
+            };
+            match writeln!(file, "{}", line) {
+                Ok(()) => break,
+                Err(e) => {
+                    debug!("FIFO reader went away: {}", e);
+                    fifo = None;
+                }
+            }
+        }
+    }
+}
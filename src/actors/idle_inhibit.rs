@@ -0,0 +1,78 @@
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+use zbus::blocking::Connection;
+
+const SCREENSAVER_SERVICE: &str = "org.freedesktop.ScreenSaver";
+const SCREENSAVER_PATH: &str = "/org/freedesktop/ScreenSaver";
+
+/// Keeps the screen awake while on air through the `org.freedesktop.ScreenSaver` inhibit API.
+///
+/// The inhibition is tied to the DBus connection, so it is also released if onair-buddy
+/// exits without going off air.
+pub struct IdleInhibitActor {
+    connection: Connection,
+    cookie: Mutex<Option<u32>>,
+    /// Set once the screensaver service turned out to be missing.
+    disabled: AtomicBool,
+}
+
+impl IdleInhibitActor {
+    pub fn new() -> Result<Self, zbus::Error> {
+        Ok(IdleInhibitActor {
+            connection: Connection::session()?,
+            cookie: Mutex::new(None),
+            disabled: AtomicBool::new(false),
+        })
+    }
+
+    fn call<B>(&self, method: &str, body: &B) -> Result<zbus::Message, zbus::Error>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        self.connection.call_method(
+            Some(SCREENSAVER_SERVICE),
+            SCREENSAVER_PATH,
+            Some(SCREENSAVER_SERVICE),
+            method,
+            body,
+        )
+    }
+}
+
+impl OnAirActor for IdleInhibitActor {
+    fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        let mut cookie = self.cookie.lock().unwrap();
+        if self.disabled.load(Ordering::Relaxed) || cookie.is_some() {
+            return Ok(());
+        }
+        let reply = match self.call("Inhibit", &(crate::APP_NAME, "On air")) {
+            Ok(reply) => reply,
+            Err(zbus::Error::MethodError(name, _, _))
+                if name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown"
+                    || name.as_str() == "org.freedesktop.DBus.Error.UnknownMethod" =>
+            {
+                warn!(
+                    "{} is not available, disabling idle inhibition",
+                    SCREENSAVER_SERVICE
+                );
+                self.disabled.store(true, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let new_cookie: u32 = reply.body().deserialize()?;
+        debug!("inhibited idle with cookie [{}]", new_cookie);
+        *cookie = Some(new_cookie);
+        Ok(())
+    }
+
+    fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        if let Some(cookie) = self.cookie.lock().unwrap().take() {
+            debug!("releasing idle inhibition [{}]", cookie);
+            self.call("UnInhibit", &(cookie,))?;
+        }
+        Ok(())
+    }
+}
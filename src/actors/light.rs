@@ -0,0 +1,364 @@
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use serde::{Deserialize, Deserializer};
+use snafu::prelude::*;
+use std::time::Duration;
+use tracing::{debug, warn};
+use url::Url;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid light url [{}]", url))]
+    InvalidLightUrl {
+        url: String,
+        source: url::ParseError,
+    },
+}
+
+/// A color written as `#rrggbb`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl<'de> Deserialize<'de> for Rgb {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let invalid = || serde::de::Error::custom(format!("invalid color [{}]", value));
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6)
+            .ok_or_else(invalid)?;
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).map_err(|_| invalid());
+        Ok(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LightKind {
+    Hue,
+    Wled,
+    Elgato,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightConfig {
+    pub kind: LightKind,
+    /// The device, or for Hue the light on the bridge, e.g.
+    /// `http://bridge.local/api/<username>/lights/1`.
+    pub url: String,
+    /// Percent, the light is switched off when off air.
+    #[serde(default = "default_brightness")]
+    pub brightness: u8,
+    /// Fade duration, rounded to tenths of a second for Hue and WLED.
+    #[serde(default)]
+    pub transition_ms: u64,
+    /// Colors by number of apps, following the number of apps instead of the transitions.
+    #[serde(default)]
+    pub levels: Vec<LightLevel>,
+}
+
+/// How the light looks while at least `apps` apps use in scope devices.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightLevel {
+    pub apps: usize,
+    pub color: Option<Rgb>,
+    /// Percent, `brightness` of the light if missing. 0 switches the light off.
+    pub brightness: Option<u8>,
+}
+
+/// What a request sets the light to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LightState {
+    on: bool,
+    brightness: u8,
+    color: Option<Rgb>,
+}
+
+fn default_brightness() -> u8 {
+    100
+}
+
+/// Switches a dimmable Philips Hue, WLED or Elgato light on and off on transitions, or sets it
+/// to the color configured for the number of apps if there are `levels`.
+pub struct LightActor {
+    url: Url,
+    agent: ureq::Agent,
+    config: LightConfig,
+}
+
+impl LightActor {
+    pub fn new(config: &LightConfig) -> Result<Self, Error> {
+        let base = config.url.trim_end_matches('/');
+        let url = match config.kind {
+            LightKind::Hue => format!("{}/state", base),
+            LightKind::Wled => format!("{}/json/state", base),
+            LightKind::Elgato => format!("{}/elgato/lights", base),
+        };
+        let url = Url::parse(&url).context(InvalidLightUrlSnafu { url: &config.url })?;
+        if config.kind == LightKind::Elgato && config.transition_ms > 0 {
+            warn!("Elgato lights don't support transitions, ignoring transition_ms");
+        }
+        let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+        let mut config = config.clone();
+        config.levels.sort_by_key(|level| level.apps);
+        Ok(LightActor { url, agent, config })
+    }
+
+    fn state(&self, on: bool) -> LightState {
+        LightState {
+            on,
+            brightness: self.config.brightness,
+            color: None,
+        }
+    }
+
+    /// The state of the highest level reached, off below the lowest.
+    fn level_state(&self, count: usize) -> LightState {
+        match self
+            .config
+            .levels
+            .iter()
+            .rev()
+            .find(|level| level.apps <= count)
+        {
+            Some(level) => {
+                let brightness = level.brightness.unwrap_or(self.config.brightness);
+                LightState {
+                    on: brightness > 0,
+                    brightness,
+                    color: level.color,
+                }
+            }
+            None => self.state(false),
+        }
+    }
+
+    /// The request setting the light, as the method and the JSON body.
+    fn request(&self, state: LightState) -> (&'static str, String) {
+        let percent = u64::from(state.brightness.min(100));
+        // Both count in steps of 100ms.
+        let transition = (self.config.transition_ms + 50) / 100;
+        match self.config.kind {
+            LightKind::Hue => {
+                let brightness = (percent * 254 / 100).max(1);
+                let color = match state.color {
+                    Some(color) => {
+                        let (x, y) = hue_xy(color);
+                        format!(", \"xy\": [{:.4}, {:.4}]", x, y)
+                    }
+                    None => String::new(),
+                };
+                let body = format!(
+                    "{{\"on\": {}, \"bri\": {}, \"transitiontime\": {}{}}}",
+                    state.on, brightness, transition, color
+                );
+                ("PUT", body)
+            }
+            LightKind::Wled => {
+                let brightness = percent * 255 / 100;
+                let color = match state.color {
+                    Some(Rgb(r, g, b)) => {
+                        format!(", \"seg\": [{{\"col\": [[{}, {}, {}]]}}]", r, g, b)
+                    }
+                    None => String::new(),
+                };
+                let body = format!(
+                    "{{\"on\": {}, \"bri\": {}, \"transition\": {}{}}}",
+                    state.on, brightness, transition, color
+                );
+                ("POST", body)
+            }
+            LightKind::Elgato => {
+                let color = match state.color {
+                    Some(color) => {
+                        let (hue, saturation) = hue_saturation(color);
+                        format!(", \"hue\": {:.1}, \"saturation\": {:.1}", hue, saturation)
+                    }
+                    None => String::new(),
+                };
+                let body = format!(
+                    "{{\"numberOfLights\": 1, \"lights\": [{{\"on\": {}, \"brightness\": {}{}}}]}}",
+                    state.on as u8, percent, color
+                );
+                ("PUT", body)
+            }
+        }
+    }
+
+    fn set(&self, state: LightState) -> Result<(), HookError> {
+        let (method, body) = self.request(state);
+        debug!("setting light [{}] to [{}]", self.url, body);
+        self.agent
+            .request_url(method, &self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+            .map_err(|e| format!("setting light [{}] failed: {}", self.url, e))?;
+        Ok(())
+    }
+}
+
+/// CIE xy coordinates of a color in the wide gamut Hue lights use.
+fn hue_xy(Rgb(r, g, b): Rgb) -> (f64, f64) {
+    let linear = |channel: u8| {
+        let value = f64::from(channel) / 255.0;
+        if value > 0.04045 {
+            ((value + 0.055) / 1.055).powf(2.4)
+        } else {
+            value / 12.92
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let x = r * 0.664511 + g * 0.154324 + b * 0.162028;
+    let y = r * 0.283881 + g * 0.668433 + b * 0.047685;
+    let z = r * 0.000088 + g * 0.072310 + b * 0.986039;
+    let sum = x + y + z;
+    if sum == 0.0 {
+        return (0.0, 0.0);
+    }
+    (x / sum, y / sum)
+}
+
+/// Hue in degrees and saturation in percent, as Elgato light strips take colors.
+fn hue_saturation(Rgb(r, g, b): Rgb) -> (f64, f64) {
+    let (r, g, b) = (f64::from(r), f64::from(g), f64::from(b));
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if delta == 0.0 {
+        return (0.0, 0.0);
+    }
+    let hue = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    (hue * 60.0, delta / max * 100.0)
+}
+
+impl OnAirActor for LightActor {
+    fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        if !self.config.levels.is_empty() {
+            return Ok(());
+        }
+        self.set(self.state(true))
+    }
+
+    fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        if !self.config.levels.is_empty() {
+            return Ok(());
+        }
+        self.set(self.state(false))
+    }
+
+    fn on_level_change(&self, count: usize) -> Result<(), HookError> {
+        if self.config.levels.is_empty() {
+            return Ok(());
+        }
+        self.set(self.level_state(count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actor(config: &str) -> LightActor {
+        LightActor::new(&toml::from_str(config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn transitions_are_sent_in_tenths_of_a_second() {
+        let hue = actor(
+            "kind = \"hue\"\nurl = \"http://bridge/api/user/lights/1\"\n\
+             brightness = 50\ntransition_ms = 500",
+        );
+        assert_eq!(hue.url.as_str(), "http://bridge/api/user/lights/1/state");
+        assert_eq!(
+            hue.request(hue.state(true)),
+            (
+                "PUT",
+                "{\"on\": true, \"bri\": 127, \"transitiontime\": 5}".to_string()
+            )
+        );
+        let wled = actor("kind = \"wled\"\nurl = \"http://wled.local/\"\ntransition_ms = 250");
+        assert_eq!(wled.url.as_str(), "http://wled.local/json/state");
+        assert_eq!(
+            wled.request(wled.state(false)),
+            (
+                "POST",
+                "{\"on\": false, \"bri\": 255, \"transition\": 3}".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn transitions_default_to_instant() {
+        let wled = actor("kind = \"wled\"\nurl = \"http://wled.local\"");
+        assert!(wled
+            .request(wled.state(true))
+            .1
+            .ends_with("\"transition\": 0}"));
+        let elgato = actor("kind = \"elgato\"\nurl = \"http://keylight.local:9123\"");
+        assert_eq!(
+            elgato.url.as_str(),
+            "http://keylight.local:9123/elgato/lights"
+        );
+        assert_eq!(
+            elgato.request(elgato.state(true)).1,
+            "{\"numberOfLights\": 1, \"lights\": [{\"on\": 1, \"brightness\": 100}]}"
+        );
+    }
+
+    #[test]
+    fn levels_map_the_number_of_apps_to_colors() {
+        let wled = actor(
+            "kind = \"wled\"\nurl = \"http://wled.local\"\n\
+             [[levels]]\napps = 2\ncolor = \"#ff0000\"\n\
+             [[levels]]\napps = 1\ncolor = \"#00ff00\"\nbrightness = 40",
+        );
+        assert_eq!(wled.level_state(0), wled.state(false));
+        assert_eq!(
+            wled.request(wled.level_state(1)).1,
+            "{\"on\": true, \"bri\": 102, \"transition\": 0, \"seg\": [{\"col\": [[0, 255, 0]]}]}"
+        );
+        let red = Some(Rgb(255, 0, 0));
+        assert_eq!(wled.level_state(2).color, red);
+        assert_eq!(wled.level_state(5).color, red);
+        let transition = Transition {
+            at: std::time::SystemTime::now(),
+            devices: Vec::new(),
+            labels: Vec::new(),
+            apps: Vec::new(),
+            scopes: Vec::new(),
+        };
+        // Unreachable, so this would fail if levels didn't take over from the transitions.
+        wled.go_on_air(&transition).unwrap();
+    }
+
+    #[test]
+    fn colors_are_converted_for_each_api() {
+        let level = "\n[[levels]]\napps = 1\ncolor = \"#ff0000\"";
+        let hue = actor(&format!(
+            "kind = \"hue\"\nurl = \"http://bridge/api/u/lights/1\"{}",
+            level
+        ));
+        assert!(hue
+            .request(hue.level_state(1))
+            .1
+            .ends_with("\"xy\": [0.7006, 0.2993]}"));
+        let elgato = actor(&format!(
+            "kind = \"elgato\"\nurl = \"http://strip:9123\"{}",
+            level
+        ));
+        assert!(elgato
+            .request(elgato.level_state(1))
+            .1
+            .ends_with("\"hue\": 0.0, \"saturation\": 100.0}]}"));
+        assert_eq!(hue_saturation(Rgb(0, 128, 255)).0.round(), 210.0);
+        assert!(toml::from_str::<LightLevel>("apps = 1\ncolor = \"red\"").is_err());
+    }
+}
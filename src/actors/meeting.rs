@@ -0,0 +1,93 @@
+use super::light::{self, LightActor, LightConfig};
+use super::slack::{SlackActor, SlackConfig};
+use super::{CompositeActor, ExecActor};
+use crate::recording_watcher::OnAirActor;
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::info;
+
+/// Turns GNOME's notification banners off and on again.
+const DND_KEY: [&str; 3] = ["gsettings", "set", "org.gnome.desktop.notifications"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeetingModeConfig {
+    pub slack: Option<SlackConfig>,
+    /// Hide desktop notifications while on air.
+    #[serde(default)]
+    pub dnd: bool,
+    #[cfg(feature = "idle-inhibit")]
+    #[serde(default)]
+    pub idle_inhibit: bool,
+    pub light: Option<LightConfig>,
+}
+
+fn show_banners(show: bool) -> Vec<String> {
+    let mut command: Vec<String> = DND_KEY.iter().map(|arg| arg.to_string()).collect();
+    command.push("show-banners".to_string());
+    command.push(show.to_string());
+    command
+}
+
+/// Builds the actors of everything configured in the meeting mode block, in the order Slack
+/// status, do not disturb, idle inhibition and light.
+pub fn preset(
+    config: &MeetingModeConfig,
+    command_timeout: Duration,
+) -> Result<CompositeActor, light::Error> {
+    let mut names = Vec::new();
+    let mut actors: Vec<Box<dyn OnAirActor>> = Vec::new();
+    if let Some(slack) = &config.slack {
+        names.push("slack status");
+        actors.push(Box::new(SlackActor::new(slack)));
+    }
+    if config.dnd {
+        names.push("do not disturb");
+        let actor = ExecActor::new(Some(show_banners(false)), Some(show_banners(true)))
+            .with_timeout(command_timeout);
+        actors.push(Box::new(actor));
+    }
+    #[cfg(feature = "idle-inhibit")]
+    if config.idle_inhibit {
+        match super::idle_inhibit::IdleInhibitActor::new() {
+            Ok(actor) => {
+                names.push("idle inhibit");
+                actors.push(Box::new(actor));
+            }
+            Err(e) => tracing::warn!("no session bus, meeting mode won't inhibit idle: {}", e),
+        }
+    }
+    if let Some(light) = &config.light {
+        names.push("light");
+        actors.push(Box::new(LightActor::new(light)?));
+    }
+    if names.is_empty() {
+        tracing::warn!("meeting_mode is enabled, but has nothing configured");
+    } else {
+        info!("Meeting mode: {}", names.join(", "));
+    }
+    Ok(CompositeActor::new(actors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_configured_parts_are_included() {
+        let config: MeetingModeConfig = toml::from_str(
+            "dnd = true\n[light]\nkind = \"wled\"\nurl = \"http://wled.local\"",
+        )
+        .unwrap();
+        assert!(config.slack.is_none());
+        assert_eq!(preset(&config, Duration::ZERO).unwrap().actors.len(), 2);
+        assert!(preset(&MeetingModeConfig::default(), Duration::ZERO)
+            .unwrap()
+            .actors
+            .is_empty());
+        assert_eq!(
+            show_banners(false),
+            ["gsettings", "set", "org.gnome.desktop.notifications", "show-banners", "false"]
+        );
+    }
+}
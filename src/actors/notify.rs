@@ -0,0 +1,130 @@
+use crate::config::ScopeConfig;
+use crate::recording_watcher::{
+    DebugActor, FanOutAlarm, HookError, NotificationConfig, NotificationContent,
+    NotificationTemplates, OnAirActor, Transition, Urgency,
+};
+use notify_rust::{Notification, NotificationHandle};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+/// Shows the same desktop notifications as the command based default actor, but talks to
+/// the notification daemon over DBus directly. Unless `replace` is turned off, a single
+/// notification is kept and updated in place.
+///
+/// If the notification daemon can't be reached over DBus, e.g. from within a sandbox, it
+/// falls back to the notifier commands.
+pub struct NotifyActor {
+    config: NotificationConfig,
+    templates: NotificationTemplates,
+    handle: Mutex<Option<NotificationHandle>>,
+    fallback: DebugActor,
+    /// Set once the notification daemon turned out to be unreachable.
+    use_fallback: AtomicBool,
+}
+
+impl NotifyActor {
+    pub fn new(config: NotificationConfig, scopes: &[ScopeConfig]) -> Self {
+        NotifyActor {
+            fallback: DebugActor::new(config.clone(), scopes),
+            templates: NotificationTemplates::new(&config, scopes),
+            config,
+            handle: Mutex::new(None),
+            use_fallback: AtomicBool::new(false),
+        }
+    }
+
+    fn notify(
+        &self,
+        content: &NotificationContent,
+        urgency: Option<Urgency>,
+    ) -> Result<(), HookError> {
+        let mut handle = self.handle.lock().unwrap();
+        if let Some(handle) = handle.as_mut().filter(|_| self.config.replace) {
+            self.apply(handle, content, urgency);
+            return Ok(handle.update()?);
+        }
+        let mut notification = Notification::new();
+        self.apply(&mut notification, content, urgency);
+        *handle = Some(notification.show()?);
+        Ok(())
+    }
+
+    fn apply(
+        &self,
+        notification: &mut Notification,
+        content: &NotificationContent,
+        urgency: Option<Urgency>,
+    ) {
+        notification.summary(&content.summary);
+        // an updated notification mustn't keep the body of the previous one
+        notification.body(content.body.as_deref().unwrap_or_default());
+        if let Some(urgency) = urgency {
+            notification.urgency(match urgency {
+                Urgency::Low => notify_rust::Urgency::Low,
+                Urgency::Normal => notify_rust::Urgency::Normal,
+                Urgency::Critical => notify_rust::Urgency::Critical,
+            });
+        }
+        if let Some(timeout) = self.config.timeout {
+            notification.timeout(timeout);
+        }
+        if let Some(icon) = &content.icon {
+            notification.icon(icon);
+        }
+    }
+}
+
+impl NotifyActor {
+    fn notify_or_fall_back(
+        &self,
+        content: &NotificationContent,
+        urgency: Option<Urgency>,
+        fallback: impl Fn(&DebugActor) -> Result<(), HookError>,
+    ) -> Result<(), HookError> {
+        if !self.use_fallback.load(Ordering::Relaxed) {
+            match self.notify(content, urgency) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!(
+                        "notification daemon is not reachable, falling back to commands: {}",
+                        e
+                    );
+                    self.use_fallback.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        fallback(&self.fallback)
+    }
+}
+
+impl OnAirActor for NotifyActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        let content = self.templates.on_air(transition);
+        self.notify_or_fall_back(&content, self.config.on_air_urgency, |fallback| {
+            fallback.go_on_air(transition)
+        })
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        let content = self.templates.off_air(transition);
+        self.notify_or_fall_back(&content, self.config.off_air_urgency, |fallback| {
+            fallback.go_off_air(transition)
+        })
+    }
+
+    fn go_warning(&self, transition: &Transition, on_air_for: Duration) -> Result<(), HookError> {
+        let content = self.templates.warning(transition, on_air_for);
+        self.notify_or_fall_back(&content, self.config.on_air_urgency, |fallback| {
+            fallback.go_warning(transition, on_air_for)
+        })
+    }
+
+    fn go_fan_out_alarm(&self, alarm: &FanOutAlarm) -> Result<(), HookError> {
+        let content = self.templates.fan_out(alarm);
+        self.notify_or_fall_back(&content, self.config.on_air_urgency, |fallback| {
+            fallback.go_fan_out_alarm(alarm)
+        })
+    }
+}
@@ -0,0 +1,167 @@
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use obws::requests::scene_items::{Id, SetEnabled};
+use obws::Client;
+use serde::Deserialize;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObsConfig {
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub password: Option<String>,
+    pub action: ObsAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObsAction {
+    /// Switch the program scene on every transition.
+    Scene { on_air: String, off_air: String },
+    /// Show a source in a scene while on air, hide it while off air.
+    Source { scene: String, source: String },
+}
+
+fn default_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_port() -> u16 {
+    4455
+}
+
+/// Performs the configured obs-websocket request on transitions.
+///
+/// obws is async, so requests run on a dedicated thread with its own runtime and never block
+/// the PipeWire loop. Failures are only logged there.
+pub struct ObsActor {
+    transitions: Sender<bool>,
+}
+
+impl ObsActor {
+    pub fn new(config: &ObsConfig) -> Self {
+        let (transitions, receiver) = mpsc::channel();
+        let config = config.clone();
+        thread::spawn(move || {
+            let runtime = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    warn!("failed to start runtime for OBS actor: {}", e);
+                    return;
+                }
+            };
+            let mut connection = ObsConnection {
+                config,
+                client: None,
+            };
+            for on_air in receiver {
+                runtime.block_on(connection.apply(on_air));
+            }
+        });
+        ObsActor { transitions }
+    }
+
+    fn send(&self, on_air: bool) -> Result<(), HookError> {
+        self.transitions
+            .send(on_air)
+            .map_err(|_| "OBS actor is no longer running".into())
+    }
+}
+
+impl OnAirActor for ObsActor {
+    fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.send(true)
+    }
+
+    fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.send(false)
+    }
+}
+
+struct ObsConnection {
+    config: ObsConfig,
+    client: Option<Client>,
+}
+
+impl ObsConnection {
+    /// Retries once with a fresh connection, as OBS may have been restarted since the last
+    /// transition.
+    async fn apply(&mut self, on_air: bool) {
+        for attempt in 1..=2 {
+            let client = match self.client.take() {
+                Some(client) => client,
+                None => match self.connect().await {
+                    Ok(client) => client,
+                    Err(e) => {
+                        warn!(
+                            "OBS at [{}:{}] is not reachable: {}",
+                            self.config.host, self.config.port, e
+                        );
+                        return;
+                    }
+                },
+            };
+            match self.request(&client, on_air).await {
+                Ok(()) => {
+                    self.client = Some(client);
+                    return;
+                }
+                Err(e) if attempt == 1 => debug!("OBS request failed, reconnecting: {}", e),
+                Err(e) => warn!("OBS request failed: {}", e),
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<Client, obws::error::Error> {
+        debug!(
+            "connecting to OBS at [{}:{}]",
+            self.config.host, self.config.port
+        );
+        Client::connect(
+            &self.config.host,
+            self.config.port,
+            self.config.password.as_deref(),
+        )
+        .await
+    }
+
+    async fn request(&self, client: &Client, on_air: bool) -> Result<(), obws::error::Error> {
+        match &self.config.action {
+            ObsAction::Scene {
+                on_air: on_air_scene,
+                off_air: off_air_scene,
+            } => {
+                let scene = if on_air { on_air_scene } else { off_air_scene };
+                client
+                    .scenes()
+                    .set_current_program_scene(scene.as_str())
+                    .await
+            }
+            ObsAction::Source { scene, source } => {
+                let item_id = client
+                    .scene_items()
+                    .id(Id {
+                        scene: scene.as_str().into(),
+                        source,
+                        search_offset: None,
+                    })
+                    .await?;
+                client
+                    .scene_items()
+                    .set_enabled(SetEnabled {
+                        scene: scene.as_str().into(),
+                        item_id,
+                        enabled: on_air,
+                    })
+                    .await
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use crate::actors::{webhook, CompositeActor, ExecActor, ShellCommandActor};
+use crate::pattern;
+use crate::recording_watcher::{FanOutAlarm, HookError, OnAirActor, Transition};
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Actors used instead of the top level ones while devices matching `devices` are linked.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// Device name patterns.
+    pub devices: Vec<String>,
+    pub on_air_cmd: Option<String>,
+    pub off_air_cmd: Option<String>,
+    pub on_air_exec: Option<Vec<String>>,
+    pub off_air_exec: Option<Vec<String>>,
+    pub webhook: Option<webhook::WebhookConfig>,
+}
+
+struct Route {
+    devices: Vec<String>,
+    actor: Box<dyn OnAirActor>,
+}
+
+/// Actors a transition was forwarded to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Targets {
+    routes: Vec<usize>,
+    default: bool,
+}
+
+/// Forwards going on air to the actors of the routes matching the linked devices, and to the
+/// default actor for devices without a route. Going off air goes to the same actors that
+/// went on air, so each indicator is switched off again by whoever switched it on.
+///
+/// In combined mode, devices linked while already on air don't change the targets until the
+/// next transition. Per device, actors newly targeted go on air and those no longer targeted
+/// go off air right away.
+pub struct RoutingActor {
+    routes: Vec<Route>,
+    default: Box<dyn OnAirActor>,
+    per_device: bool,
+    /// Unset until the first transition went on air, all actors are targeted before that.
+    active: Mutex<Option<Targets>>,
+}
+
+impl RoutingActor {
+    pub fn new(
+        routes: &[RouteConfig],
+        default: Box<dyn OnAirActor>,
+        command_timeout: Duration,
+        per_device: bool,
+    ) -> Result<Self, webhook::Error> {
+        let routes = routes
+            .iter()
+            .map(|route| {
+                Ok(Route {
+                    devices: route.devices.clone(),
+                    actor: route_actor(route, command_timeout)?,
+                })
+            })
+            .collect::<Result<_, webhook::Error>>()?;
+        Ok(RoutingActor {
+            routes,
+            default,
+            per_device,
+            active: Mutex::new(None),
+        })
+    }
+
+    fn select(&self, transition: &Transition) -> Targets {
+        let mut targets = Targets {
+            routes: Vec::new(),
+            default: transition.devices.is_empty(),
+        };
+        for device in &transition.devices {
+            let mut routed = false;
+            for (index, route) in self.routes.iter().enumerate() {
+                if pattern::matches_any(&route.devices, device) {
+                    routed = true;
+                    if !targets.routes.contains(&index) {
+                        targets.routes.push(index);
+                    }
+                }
+            }
+            targets.default |= !routed;
+        }
+        targets.routes.sort_unstable();
+        targets
+    }
+
+    fn all(&self) -> Targets {
+        Targets {
+            routes: (0..self.routes.len()).collect(),
+            default: true,
+        }
+    }
+
+    fn current(&self) -> Targets {
+        self.active
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| self.all())
+    }
+
+    /// Invokes every targeted actor, even if earlier ones fail, and reports all failures
+    /// together.
+    fn run(
+        &self,
+        targets: &Targets,
+        hook: impl Fn(&dyn OnAirActor) -> Result<(), HookError>,
+    ) -> Result<(), HookError> {
+        let routed = targets
+            .routes
+            .iter()
+            .map(|index| &self.routes[*index].actor);
+        let failures: Vec<String> = routed
+            .chain(targets.default.then_some(&self.default))
+            .filter_map(|actor| hook(actor.as_ref()).err())
+            .map(|e| e.to_string())
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("; ").into())
+        }
+    }
+}
+
+impl OnAirActor for RoutingActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        let targets = self.select(transition);
+        debug!(
+            "routing to routes {:?}, default actor: {}",
+            targets.routes, targets.default
+        );
+        let result = self.run(&targets, |actor| actor.go_on_air(transition));
+        *self.active.lock().unwrap() = Some(targets);
+        result
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run(&self.current(), |actor| actor.go_off_air(transition))
+    }
+
+    fn on_level_change(&self, count: usize) -> Result<(), HookError> {
+        self.run(&self.current(), |actor| actor.on_level_change(count))
+    }
+
+    fn go_warning(&self, transition: &Transition, on_air_for: Duration) -> Result<(), HookError> {
+        self.run(&self.current(), |actor| {
+            actor.go_warning(transition, on_air_for)
+        })
+    }
+
+    /// Routes have no alarm hooks of their own, so alarms always go to the default actor.
+    fn go_fan_out_alarm(&self, alarm: &FanOutAlarm) -> Result<(), HookError> {
+        self.default.go_fan_out_alarm(alarm)
+    }
+
+    fn on_devices_change(&self, transition: &Transition) -> Result<(), HookError> {
+        if !self.per_device {
+            return Ok(());
+        }
+        let targets = self.select(transition);
+        let mut active = self.active.lock().unwrap();
+        let previous = active.clone().unwrap_or_else(|| self.all());
+        if targets == previous {
+            return Ok(());
+        }
+        let stopped = Targets {
+            routes: previous
+                .routes
+                .iter()
+                .filter(|index| !targets.routes.contains(index))
+                .copied()
+                .collect(),
+            default: previous.default && !targets.default,
+        };
+        let started = Targets {
+            routes: targets
+                .routes
+                .iter()
+                .filter(|index| !previous.routes.contains(index))
+                .copied()
+                .collect(),
+            default: targets.default && !previous.default,
+        };
+        debug!(
+            "routes {:?} going off air, routes {:?} going on air",
+            stopped.routes, started.routes
+        );
+        let off = self.run(&stopped, |actor| actor.go_off_air(transition));
+        let on = self.run(&started, |actor| actor.go_on_air(transition));
+        *active = Some(targets);
+        match (off, on) {
+            (Err(off), Err(on)) => Err(format!("{}; {}", off, on).into()),
+            (off, on) => off.and(on),
+        }
+    }
+}
+
+fn route_actor(
+    route: &RouteConfig,
+    command_timeout: Duration,
+) -> Result<Box<dyn OnAirActor>, webhook::Error> {
+    let mut actors: Vec<(&str, Box<dyn OnAirActor>)> = Vec::new();
+    if route.on_air_cmd.is_some() || route.off_air_cmd.is_some() {
+        let actor = ShellCommandActor::new(route.on_air_cmd.clone(), route.off_air_cmd.clone())
+            .with_timeout(command_timeout);
+        actors.push(("shell", Box::new(actor)));
+    }
+    if route.on_air_exec.is_some() || route.off_air_exec.is_some() {
+        let actor = ExecActor::new(route.on_air_exec.clone(), route.off_air_exec.clone())
+            .with_timeout(command_timeout);
+        actors.push(("exec", Box::new(actor)));
+    }
+    if let Some(webhook) = &route.webhook {
+        actors.push(("webhook", Box::new(webhook::WebhookActor::new(webhook)?)));
+    }
+    let names: Vec<&str> = actors.iter().map(|(name, _)| *name).collect();
+    info!(
+        "Routing devices [{}] to: {}",
+        route.devices.join(", "),
+        if names.is_empty() {
+            "nothing".to_string()
+        } else {
+            names.join(", ")
+        }
+    );
+    let mut actors: Vec<Box<dyn OnAirActor>> = actors.into_iter().map(|(_, actor)| actor).collect();
+    Ok(match actors.len() {
+        1 => actors.remove(0),
+        _ => Box::new(CompositeActor::new(actors)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    /// Records the hooks that ran, prefixed with its name.
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Recorder {
+        fn record(&self, call: &str) -> Result<(), HookError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", self.name, call));
+            Ok(())
+        }
+    }
+
+    impl OnAirActor for Recorder {
+        fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+            self.record("on")
+        }
+
+        fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+            self.record("off")
+        }
+    }
+
+    fn routing(per_device: bool, calls: &Arc<Mutex<Vec<String>>>) -> RoutingActor {
+        let recorder = |name| {
+            Box::new(Recorder {
+                name,
+                calls: calls.clone(),
+            })
+        };
+        RoutingActor {
+            routes: vec![
+                Route {
+                    devices: vec!["Mic*".to_string()],
+                    actor: recorder("mic"),
+                },
+                Route {
+                    devices: vec!["Headset".to_string()],
+                    actor: recorder("headset"),
+                },
+            ],
+            default: recorder("default"),
+            per_device,
+            active: Mutex::new(None),
+        }
+    }
+
+    fn transition(devices: &[&str]) -> Transition {
+        let devices: Vec<String> = devices.iter().map(|device| device.to_string()).collect();
+        Transition {
+            at: SystemTime::now(),
+            labels: devices.clone(),
+            devices,
+            apps: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn combined_mode_keeps_targets_until_off_air() {
+        let calls = Arc::default();
+        let actor = routing(false, &calls);
+        actor.go_on_air(&transition(&["Mic 1"])).unwrap();
+        actor.on_devices_change(&transition(&["Mic 1", "Headset"])).unwrap();
+        actor.go_off_air(&transition(&[])).unwrap();
+        assert_eq!(*calls.lock().unwrap(), ["mic on", "mic off"]);
+    }
+
+    #[test]
+    fn per_device_mode_follows_the_linked_devices() {
+        let calls = Arc::default();
+        let actor = routing(true, &calls);
+        actor.go_on_air(&transition(&["Mic 1"])).unwrap();
+        actor.on_devices_change(&transition(&["Mic 1", "Headset"])).unwrap();
+        actor.on_devices_change(&transition(&["Headset", "Speaker"])).unwrap();
+        actor.go_off_air(&transition(&[])).unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "mic on",
+                "headset on",
+                "mic off",
+                "default on",
+                "headset off",
+                "default off"
+            ]
+        );
+    }
+}
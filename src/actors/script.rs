@@ -0,0 +1,104 @@
+use crate::actors::run_with_timeout;
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Runs scripts embedded in the config on transitions.
+///
+/// Each run writes the script to a new file in the temp directory, which is removed again once
+/// it exited. Scripts starting with `#!` are run by the interpreter named there, all others by
+/// `sh`. The state, devices, apps and labels are passed in environment variables.
+pub struct ScriptActor {
+    on_air_script: Option<String>,
+    off_air_script: Option<String>,
+    timeout: Duration,
+    runs: AtomicUsize,
+}
+
+impl ScriptActor {
+    pub fn new(
+        on_air_script: Option<String>,
+        off_air_script: Option<String>,
+        timeout: Duration,
+    ) -> Self {
+        ScriptActor {
+            on_air_script,
+            off_air_script,
+            timeout,
+            runs: AtomicUsize::new(0),
+        }
+    }
+
+    fn run(
+        &self,
+        script: &Option<String>,
+        state: &str,
+        transition: &Transition,
+    ) -> Result<(), HookError> {
+        let Some(script) = script else {
+            return Ok(());
+        };
+        let run = self.runs.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "onair-buddy-{}-{}-{}.script",
+            process::id(),
+            state,
+            run
+        ));
+        write_script(&path, script)?;
+        let mut command = if script.starts_with("#!") {
+            Command::new(&path)
+        } else {
+            let mut command = Command::new("sh");
+            command.arg(&path);
+            command
+        };
+        command
+            .env("ONAIR_STATE", state)
+            .env("ONAIR_DEVICES", transition.devices.join(", "))
+            .env("ONAIR_APPS", transition.apps.join(", "))
+            .env("ONAIR_LABEL", transition.label());
+        info!("running {} air script", state);
+        let status = run_with_timeout(&mut command, self.timeout);
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("failed to remove script [{}]: {}", path.display(), e);
+        }
+        let status = status?;
+        if !status.success() {
+            return Err(format!("{} air script exited with [{}]", state, status).into());
+        }
+        Ok(())
+    }
+}
+
+impl OnAirActor for ScriptActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run(&self.on_air_script, "on", transition)
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.run(&self.off_air_script, "off", transition)
+    }
+}
+
+/// Only the owner may read and run the script, and an existing file is never reused.
+fn write_script(path: &Path, script: &str) -> Result<(), HookError> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(path)
+        .map_err(|e| format!("failed to create script [{}]: {}", path.display(), e))?;
+    if let Err(e) = file.write_all(script.as_bytes()) {
+        drop(file);
+        let _ = fs::remove_file(path);
+        return Err(format!("failed to write script [{}]: {}", path.display(), e).into());
+    }
+    Ok(())
+}
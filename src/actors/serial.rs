@@ -0,0 +1,105 @@
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use serde::Deserialize;
+use serialport::SerialPort;
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How long a write may block, e.g. if the device stopped reading.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SerialConfig {
+    pub path: String,
+    #[serde(default = "default_baud_rate")]
+    pub baud_rate: u32,
+    #[serde(default = "default_on_air")]
+    pub on_air: String,
+    #[serde(default = "default_off_air")]
+    pub off_air: String,
+}
+
+fn default_baud_rate() -> u32 {
+    9600
+}
+
+fn default_on_air() -> String {
+    "1\n".to_string()
+}
+
+fn default_off_air() -> String {
+    "0\n".to_string()
+}
+
+/// Writes the configured strings to a serial port on transitions, e.g. for a sign driven by
+/// a microcontroller.
+///
+/// The port stays open between transitions. If the device isn't there or a write fails, the
+/// port is opened again on the next transition, so the sign can be unplugged at any time.
+pub struct SerialActor {
+    config: SerialConfig,
+    port: Mutex<Option<Box<dyn SerialPort>>>,
+}
+
+impl SerialActor {
+    pub fn new(config: &SerialConfig) -> Self {
+        let actor = SerialActor {
+            config: config.clone(),
+            port: Mutex::new(None),
+        };
+        match actor.open() {
+            Ok(port) => {
+                info!("Opened serial device [{}]", config.path);
+                *actor.port.lock().unwrap() = Some(port);
+            }
+            Err(e) => warn!(
+                "serial device [{}] is not available, trying again on the next transition: {}",
+                config.path, e
+            ),
+        }
+        actor
+    }
+
+    fn open(&self) -> Result<Box<dyn SerialPort>, serialport::Error> {
+        serialport::new(&self.config.path, self.config.baud_rate)
+            .timeout(WRITE_TIMEOUT)
+            .open()
+    }
+
+    fn write(&self, data: &str) -> Result<(), HookError> {
+        let mut port = self.port.lock().unwrap();
+        if let Some(open) = port.as_mut() {
+            match write_all(open.as_mut(), data) {
+                Ok(()) => return Ok(()),
+                Err(e) => debug!(
+                    "writing to serial device [{}] failed, opening it again: {}",
+                    self.config.path, e
+                ),
+            }
+        }
+        *port = None;
+        let mut reopened = self
+            .open()
+            .map_err(|e| format!("failed to open serial device [{}]: {}", self.config.path, e))?;
+        write_all(reopened.as_mut(), data)?;
+        *port = Some(reopened);
+        Ok(())
+    }
+}
+
+impl OnAirActor for SerialActor {
+    fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.write(&self.config.on_air)
+    }
+
+    fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.write(&self.config.off_air)
+    }
+}
+
+fn write_all(port: &mut dyn SerialPort, data: &str) -> io::Result<()> {
+    port.write_all(data.as_bytes())?;
+    port.flush()
+}
@@ -0,0 +1,193 @@
+use crate::recording_watcher::{json_escape, HookError, OnAirActor, Transition};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::debug;
+
+const SLACK_API: &str = "https://slack.com/api";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Snoozes are ended when going off air, this only limits them if that never happens.
+const SNOOZE_MINUTES: u32 = 480;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlackConfig {
+    /// User token with the `users.profile:write` and, for `snooze`, `dnd:write` scopes.
+    pub token: String,
+    #[serde(default = "default_status_text")]
+    pub status_text: String,
+    #[serde(default = "default_status_emoji")]
+    pub status_emoji: String,
+    /// Pause Slack notifications while on air.
+    #[serde(default)]
+    pub snooze: bool,
+}
+
+fn default_status_text() -> String {
+    "In a meeting".to_string()
+}
+
+fn default_status_emoji() -> String {
+    ":headphones:".to_string()
+}
+
+/// Sets the Slack status while on air and clears it when going off air.
+pub struct SlackActor {
+    api: String,
+    agent: ureq::Agent,
+    config: SlackConfig,
+}
+
+impl SlackActor {
+    pub fn new(config: &SlackConfig) -> Self {
+        SlackActor {
+            api: SLACK_API.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            config: config.clone(),
+        }
+    }
+
+    /// Slack answers failed calls with `200 OK` too, but `"ok": false` in the body.
+    fn call(&self, method: &str, body: &str) -> Result<(), HookError> {
+        debug!("calling Slack [{}] with [{}]", method, body);
+        let response = self
+            .agent
+            .post(&format!("{}/{}", self.api, method))
+            .set("Authorization", &format!("Bearer {}", self.config.token))
+            .set("Content-Type", "application/json; charset=utf-8")
+            .send_string(body)
+            .map_err(|e| format!("Slack call [{}] failed: {}", method, e))?
+            .into_string()?;
+        if !response.replace(' ', "").contains("\"ok\":true") {
+            return Err(format!("Slack call [{}] failed: {}", method, response).into());
+        }
+        Ok(())
+    }
+
+    fn set_status(&self, text: &str, emoji: &str) -> Result<(), HookError> {
+        let body = format!(
+            "{{\"profile\": {{\"status_text\": \"{}\", \"status_emoji\": \"{}\", \
+             \"status_expiration\": 0}}}}",
+            json_escape(text),
+            json_escape(emoji)
+        );
+        self.call("users.profile.set", &body)
+    }
+}
+
+impl OnAirActor for SlackActor {
+    fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.set_status(&self.config.status_text, &self.config.status_emoji)?;
+        if self.config.snooze {
+            let body = format!("{{\"num_minutes\": {}}}", SNOOZE_MINUTES);
+            self.call("dnd.setSnooze", &body)?;
+        }
+        Ok(())
+    }
+
+    fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.set_status("", "")?;
+        if self.config.snooze {
+            self.call("dnd.endSnooze", "{}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::SystemTime;
+
+    /// Answers each request with the next response and returns the request lines and bodies.
+    fn serve(responses: &'static [&'static str]) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                requests.push(format!("{}{}", request, String::from_utf8(body).unwrap()));
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    response.len(),
+                    response
+                );
+                reader.get_mut().write_all(reply.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (api, server)
+    }
+
+    fn actor(api: String, config: &str) -> SlackActor {
+        let mut actor = SlackActor::new(&toml::from_str(config).unwrap());
+        actor.api = api;
+        actor
+    }
+
+    fn transition() -> Transition {
+        Transition {
+            at: SystemTime::now(),
+            devices: Vec::new(),
+            labels: Vec::new(),
+            apps: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sets_and_clears_the_status_and_snooze() {
+        let (api, server) = serve(&["{\"ok\": true}"; 4]);
+        let actor = actor(
+            api,
+            "token = \"xoxp-1\"\nstatus_text = \"On \\\"air\\\"\"\nsnooze = true",
+        );
+        actor.go_on_air(&transition()).unwrap();
+        actor.go_off_air(&transition()).unwrap();
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[0],
+            "POST /users.profile.set HTTP/1.1\r\n\
+             {\"profile\": {\"status_text\": \"On \\\"air\\\"\", \
+             \"status_emoji\": \":headphones:\", \"status_expiration\": 0}}"
+        );
+        assert_eq!(
+            requests[1],
+            "POST /dnd.setSnooze HTTP/1.1\r\n{\"num_minutes\": 480}"
+        );
+        assert!(
+            requests[2].contains("\"status_text\": \"\""),
+            "{}",
+            requests[2]
+        );
+        assert_eq!(requests[3], "POST /dnd.endSnooze HTTP/1.1\r\n{}");
+    }
+
+    #[test]
+    fn errors_in_the_body_fail_the_hook() {
+        let (api, server) = serve(&["{\"ok\": false, \"error\": \"invalid_auth\"}"]);
+        let actor = actor(api, "token = \"xoxp-1\"");
+        let error = actor.go_on_air(&transition()).unwrap_err();
+        assert!(error.to_string().contains("invalid_auth"), "{}", error);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+use crate::recording_watcher::{json_escape, HookError, OnAirActor, Transition};
+use serde::Deserialize;
+use snafu::prelude::*;
+use std::collections::BTreeMap;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::debug;
+use url::Url;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid webhook url [{}]", url))]
+    InvalidUrl {
+        url: String,
+        source: url::ParseError,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Hostnames and IPv6 literals in brackets are supported, e.g. `http://[::1]:9123/hook`.
+    pub url: String,
+    /// Body templates, `{device}`, `{app}` and `{ts}` are substituted.
+    pub on_air_body: Option<String>,
+    pub off_air_body: Option<String>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// Additional headers sent with every request, e.g. for authentication.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+/// POSTs `{"on_air": true}` or `{"on_air": false}`, or the configured bodies, to the
+/// configured url on transitions.
+pub struct WebhookActor {
+    url: Url,
+    agent: ureq::Agent,
+    config: WebhookConfig,
+}
+
+impl WebhookActor {
+    pub fn new(config: &WebhookConfig) -> Result<Self, Error> {
+        let url = Url::parse(&config.url).context(InvalidUrlSnafu { url: &config.url })?;
+        let agent = ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build();
+        Ok(WebhookActor {
+            url,
+            agent,
+            config: config.clone(),
+        })
+    }
+
+    fn post(
+        &self,
+        on_air: bool,
+        template: &Option<String>,
+        transition: &Transition,
+    ) -> Result<(), HookError> {
+        debug!("posting on air state [{}] to [{}]", on_air, self.url);
+        let body = match template {
+            Some(template) => self.render(template, transition),
+            None => format!("{{\"on_air\": {}}}", on_air),
+        };
+        let mut request = self
+            .agent
+            .request_url("POST", &self.url)
+            .set("Content-Type", &self.config.content_type);
+        for (name, value) in &self.config.headers {
+            request = request.set(name, value);
+        }
+        request
+            .send_string(&body)
+            .map_err(|e| format!("webhook call to [{}] failed: {}", self.url, e))?;
+        Ok(())
+    }
+
+    /// Values are escaped for JSON string literals if the body is JSON, so device names
+    /// containing quotes can't break it.
+    fn render(&self, template: &str, transition: &Transition) -> String {
+        let is_json = self.config.content_type.contains("json");
+        let value = |value: String| if is_json { json_escape(&value) } else { value };
+        let ts = transition
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+        template
+            .replace("{device}", &value(transition.devices.join(", ")))
+            .replace("{app}", &value(transition.apps.join(", ")))
+            .replace("{ts}", &ts.to_string())
+    }
+}
+
+impl OnAirActor for WebhookActor {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.post(true, &self.config.on_air_body, transition)
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.post(false, &self.config.off_air_body, transition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::SystemTime;
+
+    fn config(url: &str) -> WebhookConfig {
+        toml::from_str(&format!("url = \"{}\"", url)).unwrap()
+    }
+
+    #[test]
+    fn accepts_ipv6_literals_and_hostnames() {
+        let actor = WebhookActor::new(&config("http://[::1]:9123/on-air")).unwrap();
+        assert_eq!(actor.url.host(), Some(url::Host::Ipv6("::1".parse().unwrap())));
+        assert_eq!(actor.url.port(), Some(9123));
+        let actor = WebhookActor::new(&config("http://sign.local:8080/on-air")).unwrap();
+        assert_eq!(actor.url.host_str(), Some("sign.local"));
+        assert!(WebhookActor::new(&config("http://::1:9123/on-air")).is_err());
+    }
+
+    #[test]
+    fn posts_to_ipv6_loopback() {
+        let listener = TcpListener::bind("[::1]:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with('}') {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.push_str(&String::from_utf8_lossy(&buffer[..read]));
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            request
+        });
+        let actor =
+            WebhookActor::new(&config(&format!("http://[::1]:{}/on-air", port))).unwrap();
+        let transition = Transition {
+            at: SystemTime::now(),
+            devices: vec!["Mic".to_string()],
+            labels: vec!["Mic".to_string()],
+            apps: vec!["App".to_string()],
+            scopes: Vec::new(),
+        };
+        actor.go_on_air(&transition).unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /on-air HTTP/1.1\r\n"), "{}", request);
+        assert!(request.ends_with("\r\n\r\n{\"on_air\": true}"), "{}", request);
+    }
+}
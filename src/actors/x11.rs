@@ -0,0 +1,60 @@
+use crate::recording_watcher::{HookError, OnAirActor, Transition};
+use tracing::debug;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, PropMode, Window};
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+/// Root window property holding `1` while on air and `0` otherwise.
+pub const PROPERTY: &str = "_ONAIR_BUDDY_STATE";
+
+/// Publishes the state as a property of the X11 root window, for panel scripts reading it
+/// with `xprop -root _ONAIR_BUDDY_STATE`.
+pub struct X11PropertyActor {
+    connection: RustConnection,
+    root: Window,
+    atom: Atom,
+}
+
+impl X11PropertyActor {
+    /// Fails if no X server is reachable through `DISPLAY`, e.g. in a Wayland only session.
+    pub fn new() -> Result<Self, HookError> {
+        let (connection, screen) = x11rb::connect(None)?;
+        let root = connection.setup().roots[screen].root;
+        let atom = connection
+            .intern_atom(false, PROPERTY.as_bytes())?
+            .reply()?
+            .atom;
+        let actor = X11PropertyActor {
+            connection,
+            root,
+            atom,
+        };
+        // readers shouldn't see the state of an earlier instance
+        actor.set(false)?;
+        Ok(actor)
+    }
+
+    fn set(&self, on_air: bool) -> Result<(), HookError> {
+        debug!("setting {} to [{}]", PROPERTY, u8::from(on_air));
+        self.connection.change_property32(
+            PropMode::REPLACE,
+            self.root,
+            self.atom,
+            AtomEnum::CARDINAL,
+            &[u32::from(on_air)],
+        )?;
+        self.connection.flush()?;
+        Ok(())
+    }
+}
+
+impl OnAirActor for X11PropertyActor {
+    fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.set(true)
+    }
+
+    fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+        self.set(false)
+    }
+}
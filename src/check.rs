@@ -0,0 +1,206 @@
+use crate::config::Config;
+use crate::pattern;
+use crate::recording_watcher::{self, DEVICE_SCOPE_PREFIX, ID_SCOPE_PREFIX};
+use pipewire::types::ObjectType;
+use pipewire::{Context, MainLoop};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// A node or device PipeWire announced, with all names entries are matched against.
+struct Object {
+    id: u32,
+    names: Vec<String>,
+    monitor: bool,
+    source_class: bool,
+}
+
+impl Object {
+    fn describe(&self, kind: &str) -> String {
+        format!(
+            "{} [{}] {}",
+            kind,
+            self.id,
+            self.names.first().map_or("(unnamed)", |name| name.as_str())
+        )
+    }
+}
+
+/// Connects once and prints which of the current nodes each entry of `devices_in_scope`,
+/// `scopes` and `devices_ignored` matches. Fails if an in scope entry matches nothing.
+pub fn run(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let (nodes, devices) = enumerate(config)?;
+    let mut unmatched = 0;
+
+    println!("devices_in_scope:");
+    for entry in sorted(&config.devices_in_scope) {
+        let matches = scope_matches(config, entry, &nodes, &devices);
+        unmatched += usize::from(matches.is_empty());
+        print_entry(entry, &matches);
+    }
+    for scope in &config.scopes {
+        println!("scopes ({}):", scope.name.as_deref().unwrap_or("unnamed"));
+        for entry in &scope.devices {
+            let matches: Vec<String> = nodes
+                .iter()
+                .filter(|node| classified_as_source(config, node))
+                .filter(|node| node.names.iter().any(|name| pattern::matches(entry, name)))
+                .map(|node| node.describe("node"))
+                .collect();
+            unmatched += usize::from(matches.is_empty());
+            print_entry(entry, &matches);
+        }
+    }
+    println!("devices_ignored:");
+    for entry in sorted(&config.devices_ignored) {
+        let matches: Vec<String> = nodes
+            .iter()
+            .filter(|node| match entry.strip_prefix(ID_SCOPE_PREFIX) {
+                Some(id) => id.trim().parse() == Ok(node.id),
+                None => node.names.iter().any(|name| pattern::matches(entry, name)),
+            })
+            .map(|node| node.describe("node"))
+            .collect();
+        print_entry(entry, &matches);
+    }
+
+    if unmatched > 0 {
+        return Err(format!("{} in scope entries match no node", unmatched).into());
+    }
+    Ok(())
+}
+
+/// Nodes and devices announced right after connecting.
+fn enumerate(config: &Config) -> Result<(Vec<Object>, Vec<Object>), Box<dyn std::error::Error>> {
+    let mainloop = MainLoop::new()?;
+    let context = Context::new(&mainloop)?;
+    let core = recording_watcher::connect_core(&context, config.remote.as_deref())?;
+    let registry = core.get_registry()?;
+
+    let nodes: Rc<RefCell<Vec<Object>>> = Rc::default();
+    let devices: Rc<RefCell<Vec<Object>>> = Rc::default();
+    let registry_nodes = nodes.clone();
+    let registry_devices = devices.clone();
+    let _registry_listener = registry
+        .add_listener_local()
+        .global(move |global| {
+            let Some(props) = &global.props else {
+                return;
+            };
+            match global.type_ {
+                ObjectType::Node => registry_nodes.borrow_mut().push(Object {
+                    id: global.id,
+                    names: recording_watcher::get_all_names(props)
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect(),
+                    monitor: recording_watcher::is_monitor(props),
+                    source_class: recording_watcher::is_source_class(props),
+                }),
+                ObjectType::Device => registry_devices.borrow_mut().push(Object {
+                    id: global.id,
+                    names: recording_watcher::get_device_names(props),
+                    monitor: false,
+                    source_class: false,
+                }),
+                _ => {}
+            }
+        })
+        .register();
+    recording_watcher::run_until_enumerated(&mainloop, &core)?;
+
+    let nodes = nodes.take();
+    let devices = devices.take();
+    Ok((nodes, devices))
+}
+
+/// Whether the watcher puts a node in scope when one of its names matches, see
+/// `ignore_monitors` and `require_source_class`.
+fn classified_as_source(config: &Config, node: &Object) -> bool {
+    !(config.ignore_monitors && node.monitor) && (!config.require_source_class || node.source_class)
+}
+
+/// What an entry of `devices_in_scope` matches, following the same rules as the watcher.
+fn scope_matches(
+    config: &Config,
+    entry: &str,
+    nodes: &[Object],
+    devices: &[Object],
+) -> Vec<String> {
+    if let Some(id) = entry.strip_prefix(ID_SCOPE_PREFIX) {
+        return nodes
+            .iter()
+            .filter(|node| id.trim().parse() == Ok(node.id))
+            .map(|node| node.describe("node"))
+            .collect();
+    }
+    if let Some(device_pattern) = entry.strip_prefix(DEVICE_SCOPE_PREFIX) {
+        return devices
+            .iter()
+            .filter(|device| {
+                device
+                    .names
+                    .iter()
+                    .any(|name| pattern::matches(device_pattern, name))
+            })
+            .map(|device| device.describe("device"))
+            .collect();
+    }
+    nodes
+        .iter()
+        .filter(|node| classified_as_source(config, node))
+        .filter(|node| node.names.iter().any(|name| pattern::matches(entry, name)))
+        .map(|node| node.describe("node"))
+        .collect()
+}
+
+fn sorted(entries: &HashSet<String>) -> Vec<&String> {
+    let mut entries: Vec<&String> = entries.iter().collect();
+    entries.sort();
+    entries
+}
+
+fn print_entry(entry: &str, matches: &[String]) {
+    if matches.is_empty() {
+        println!("  [{}]: no match", entry);
+    } else {
+        println!("  [{}]: {}", entry, matches.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u32, name: &str, monitor: bool, source_class: bool) -> Object {
+        Object {
+            id,
+            names: vec![name.to_string()],
+            monitor,
+            source_class,
+        }
+    }
+
+    #[test]
+    fn names_only_match_nodes_the_watcher_would_classify() {
+        let nodes = [
+            node(1, "Mic", false, true),
+            node(2, "Mic", true, true),
+            node(3, "Mic", false, false),
+        ];
+        let config = Config::default();
+        assert_eq!(scope_matches(&config, "Mic", &nodes, &[]), ["node [1] Mic"]);
+        assert_eq!(scope_matches(&config, "M*", &nodes, &[]), ["node [1] Mic"]);
+        let config = Config {
+            ignore_monitors: false,
+            require_source_class: false,
+            ..Config::default()
+        };
+        assert_eq!(
+            scope_matches(&config, "Mic", &nodes, &[]),
+            ["node [1] Mic", "node [2] Mic", "node [3] Mic"]
+        );
+        // ids are taken as they are
+        assert_eq!(scope_matches(&Config::default(), "id:3", &nodes, &[]), ["node [3] Mic"]);
+    }
+}
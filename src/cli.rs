@@ -0,0 +1,108 @@
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Parser)]
+#[command(
+    name = crate::APP_NAME,
+    version,
+    about = "Runs hooks when a watched microphone is in use"
+)]
+pub struct Cli {
+    /// Config file to use instead of ~/.config/onair-buddy/config.toml, "-" reads stdin
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Start from a built-in set of defaults, e.g. "any-mic"
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Device name to watch, can be given multiple times
+    #[arg(long = "device", value_name = "NAME")]
+    pub devices_in_scope: Vec<String>,
+
+    /// Node name whose links should never count, can be given multiple times
+    #[arg(long = "ignore", value_name = "NAME")]
+    pub devices_ignored: Vec<String>,
+
+    /// Shell command to run when going on air
+    #[arg(long, value_name = "CMD")]
+    pub on_air_cmd: Option<String>,
+
+    /// Shell command to run when going off air
+    #[arg(long, value_name = "CMD")]
+    pub off_air_cmd: Option<String>,
+
+    /// Only log errors, hooks and the control socket keep working
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// Where logs go, journald falls back to stderr if the journal is not available
+    #[arg(long, value_enum, default_value_t = LogTarget::Stderr, value_name = "TARGET")]
+    pub log_target: LogTarget,
+
+    /// Print a line per transition to stdout, logs go to stderr
+    #[arg(long)]
+    pub emit_events: bool,
+
+    /// Write on and off lines to this named pipe, created if needed
+    #[arg(long, value_name = "PATH")]
+    pub fifo: Option<PathBuf>,
+
+    /// Accept commands like pause and resume on this Unix socket
+    #[arg(long, value_name = "PATH")]
+    pub control_socket: Option<PathBuf>,
+
+    /// Wait this long before connecting to PipeWire, in seconds or e.g. "1500ms"
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub startup_delay: Option<Duration>,
+
+    /// PipeWire remote to connect to, e.g. for a system wide or nested instance
+    #[arg(long, value_name = "NAME")]
+    pub remote: Option<String>,
+
+    /// Print the state and active links of the instance listening on the control socket and exit
+    #[arg(long)]
+    pub status: bool,
+
+    /// Print version and PipeWire connection details for bug reports and exit
+    #[arg(long)]
+    pub diagnose: bool,
+
+    /// Print which current nodes the scope and ignore entries match and exit, failing if an
+    /// in scope entry matches nothing
+    #[arg(long)]
+    pub check_config: bool,
+
+    /// Write all registry events to this file, for attaching to bug reports
+    #[arg(long, value_name = "FILE")]
+    pub record_events: Option<PathBuf>,
+
+    /// Replay the events recorded with --record-events instead of connecting to PipeWire
+    #[arg(long, value_name = "FILE", conflicts_with = "simulate")]
+    pub replay: Option<PathBuf>,
+
+    /// Replay the timed events from a script instead of connecting to PipeWire
+    #[arg(long, hide = true, value_name = "FILE")]
+    pub simulate: Option<PathBuf>,
+
+    /// Show a tray icon reflecting the on air state
+    #[cfg(feature = "tray")]
+    #[arg(long)]
+    pub tray: bool,
+}
+
+/// Plain numbers are seconds, everything else is parsed as a humantime duration.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    match value.parse::<f64>() {
+        Ok(secs) => Duration::try_from_secs_f64(secs).map_err(|e| e.to_string()),
+        Err(_) => humantime_serde::re::humantime::parse_duration(value).map_err(|e| e.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogTarget {
+    Stderr,
+    /// The systemd journal, with structured fields for transitions.
+    Journald,
+}
@@ -0,0 +1,797 @@
+#[cfg(feature = "ble")]
+use crate::actors::ble::BleConfig;
+use crate::actors::light::LightConfig;
+use crate::actors::meeting::MeetingModeConfig;
+#[cfg(feature = "obs")]
+use crate::actors::obs::ObsConfig;
+use crate::actors::routing::RouteConfig;
+#[cfg(feature = "serial")]
+use crate::actors::serial::SerialConfig;
+use crate::actors::webhook::WebhookConfig;
+use crate::actors::DEFAULT_COMMAND_TIMEOUT;
+use crate::cli::Cli;
+use crate::hours::HoursWindow;
+use crate::http::HttpConfig;
+use crate::recording_watcher::{NotificationConfig, NotificationTemplate};
+use serde::Deserialize;
+use snafu::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
+
+pub const ENV_DEVICES_IN_SCOPE: &str = "ONAIR_DEVICES_IN_SCOPE";
+pub const ENV_DEVICES_IGNORED: &str = "ONAIR_DEVICES_IGNORED";
+pub const ENV_ON_AIR_CMD: &str = "ONAIR_ON_AIR_CMD";
+pub const ENV_OFF_AIR_CMD: &str = "ONAIR_OFF_AIR_CMD";
+pub const ENV_REMOTE: &str = "PIPEWIRE_REMOTE";
+
+/// Passed as the config file to read the config from stdin.
+pub const STDIN_CONFIG: &str = "-";
+
+/// Keys holding paths of list files, relative ones are resolved against the directory of the
+/// config file setting them.
+const LIST_FILE_KEYS: [&str; 2] = ["devices_in_scope_file", "devices_ignored_file"];
+
+/// Named bundles of defaults, applied before the config files.
+const PROFILES: &[(&str, &str)] = &[("any-mic", include_str!("profiles/any-mic.toml"))];
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Failed to read config file [{}]", path.display()))]
+    ReadConfig {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Failed to parse config file [{}]: {}", path.display(), source))]
+    ParseConfig {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[snafu(display("Failed to parse config snippet: {}", source))]
+    ParseSnippet { source: toml::de::Error },
+    #[snafu(display("Failed to read config directory [{}]", path.display()))]
+    ReadConfigDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Invalid configuration: {}", source))]
+    InvalidConfig { source: toml::de::Error },
+    #[snafu(display("Unknown profile [{}], available profiles: {}", name, available))]
+    UnknownProfile { name: String, available: String },
+    #[snafu(display("Failed to read list file [{}]", path.display()))]
+    ReadListFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("%label% is not replaced in [{}], use \"$ONAIR_LABEL\" instead", key))]
+    LabelPlaceholder { key: String },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub devices_in_scope: HashSet<String>,
+    pub devices_ignored: HashSet<String>,
+    /// File with further `devices_in_scope` entries, one per line.
+    pub devices_in_scope_file: Option<PathBuf>,
+    /// File with further `devices_ignored` entries, one per line.
+    pub devices_ignored_file: Option<PathBuf>,
+    /// `media.role` patterns, links to nodes with a matching role count whatever the device.
+    pub roles_in_scope: HashSet<String>,
+    /// Put all capture devices in scope, except for monitors of outputs.
+    pub scope_all_sources: bool,
+    /// Put whatever source is currently the default one in scope.
+    pub scope_default_source: bool,
+    /// Count cameras like microphones, for `scope_all_sources` and `require_audio_ports`.
+    pub include_video: bool,
+    /// Never put monitors of outputs in scope, they record what is played.
+    pub ignore_monitors: bool,
+    /// Only put nodes in scope by name if their media class is a source, never e.g. speakers.
+    pub require_source_class: bool,
+    /// Also count links that play audio into a device, like an app feeding a speaker.
+    pub count_playback_links: bool,
+    /// Device name patterns in order of preference, only the first present one is in scope.
+    pub device_priority: Vec<String>,
+    pub on_air_cmd: Option<String>,
+    pub off_air_cmd: Option<String>,
+    /// Shell command to run when having been on air for longer than `on_air_warning` allows.
+    pub warning_cmd: Option<String>,
+    /// Shell command to run when a device is linked to fewer or more apps than `fan_out` expects.
+    pub fan_out_cmd: Option<String>,
+    /// Program and arguments to run without a shell when going on air.
+    pub on_air_exec: Option<Vec<String>>,
+    pub off_air_exec: Option<Vec<String>>,
+    /// Script to run when going on air, by `sh` or the interpreter in its `#!` line.
+    pub on_air_script: Option<String>,
+    pub off_air_script: Option<String>,
+    /// Commands and programs still running after this long are killed, zero waits forever.
+    #[serde(with = "humantime_serde")]
+    pub command_timeout: Duration,
+    pub malformed_links: MalformedLinkPolicy,
+    #[serde(with = "humantime_serde")]
+    pub on_air_delay: Duration,
+    /// Stay on air for at least this long before going off air again, e.g. for relays.
+    #[serde(with = "humantime_serde")]
+    pub min_on_dwell: Duration,
+    /// Stay off air for at least this long before going on air again.
+    #[serde(with = "humantime_serde")]
+    pub min_off_dwell: Duration,
+    /// Evaluate bursts of link events within this long once, after the burst.
+    #[serde(with = "humantime_serde")]
+    pub coalesce_window: Duration,
+    /// Local times hooks run at, like `mon-fri 09:00-18:00`; always when empty.
+    pub active_hours: Vec<HoursWindow>,
+    /// Time to wait before connecting, for logins racing with PipeWire's device enumeration.
+    #[serde(with = "humantime_serde")]
+    pub startup_delay: Duration,
+    /// Connect again after this long if PipeWire doesn't announce any nodes.
+    #[serde(with = "humantime_serde")]
+    pub empty_graph_retry: Option<Duration>,
+    /// Go off air and connect again after hearing nothing from PipeWire for this long.
+    #[serde(with = "humantime_serde")]
+    pub presence_timeout: Option<Duration>,
+    pub webhook: Option<WebhookConfig>,
+    pub light: Option<LightConfig>,
+    /// Slack status, do not disturb, idle inhibition and a light together.
+    pub meeting_mode: Option<MeetingModeConfig>,
+    pub notification: NotificationConfig,
+    #[cfg(feature = "obs")]
+    pub obs: Option<ObsConfig>,
+    #[cfg(feature = "serial")]
+    pub serial: Option<SerialConfig>,
+    #[cfg(feature = "ble")]
+    pub ble: Option<BleConfig>,
+    pub emit_events: bool,
+    /// Named pipe to write `on` and `off` lines to, created if it doesn't exist.
+    pub fifo: Option<PathBuf>,
+    #[cfg(feature = "idle-inhibit")]
+    pub idle_inhibit: bool,
+    /// Publish the state as a property of the X11 root window.
+    #[cfg(feature = "x11")]
+    pub x11_property: bool,
+    /// Only count links once PipeWire reports them as active, paused links are off air.
+    pub require_active_links: bool,
+    /// Only count links to nodes that are live streams, for ghost links that don't stream.
+    pub require_live_streams: bool,
+    /// Skip links whose output port doesn't carry audio, e.g. MIDI from a controller.
+    pub require_audio_ports: bool,
+    /// Run the on air hooks while off air and vice versa, e.g. for an availability light.
+    pub invert: bool,
+    /// Run the off air hooks when exiting while on air, so the indicator doesn't stay lit.
+    pub run_off_air_on_shutdown: bool,
+    pub smoothing: Option<SmoothingConfig>,
+    /// Number of in scope links needed to go on air.
+    pub min_active_links: usize,
+    pub link_count_scope: LinkCountScope,
+    /// Links only count towards going on air once they existed for this long.
+    #[serde(with = "humantime_serde")]
+    pub min_link_age: Duration,
+    pub on_air_warning: Option<WarningConfig>,
+    /// Expected number of active links, keyed by device name pattern.
+    pub fan_out: BTreeMap<String, FanOutConfig>,
+    /// Order in which the top level actors run, lower first, keyed by actor name.
+    pub actor_priority: BTreeMap<String, i32>,
+    /// Friendly names for devices, keyed by device name pattern.
+    pub labels: BTreeMap<String, String>,
+    pub scopes: Vec<ScopeConfig>,
+    /// Checked in order for every link, the first matching rule decides.
+    pub link_rules: Vec<LinkRule>,
+    /// Actors for specific devices, the top level actors handle all other devices.
+    pub routes: Vec<RouteConfig>,
+    /// Whether routes follow the tally as a whole or each their own devices.
+    pub tally: TallyMode,
+    pub unknown_app_policy: UnknownAppPolicy,
+    /// Number of recent transitions to keep for the status, zero keeps none.
+    pub history_size: usize,
+    /// Log the number and length of on air sessions when exiting.
+    pub session_summary: bool,
+    /// Unix socket to accept commands on, see the `control` module.
+    pub control_socket: Option<PathBuf>,
+    /// Reject control connections from other users than the one running onair-buddy.
+    pub control_same_user_only: bool,
+    /// File that forces on air while it contains `1`, see the `flag_file` module.
+    pub flag_file: Option<PathBuf>,
+    /// Serve the status over HTTP, see the `http` module.
+    pub http: Option<HttpConfig>,
+    /// PipeWire remote to connect to, the default remote is used if not set.
+    pub remote: Option<String>,
+}
+
+/// Devices that only count as on air while they are linked to one of the given apps.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScopeConfig {
+    /// Only used in logs.
+    pub name: Option<String>,
+    /// Node name patterns.
+    pub devices: Vec<String>,
+    /// Patterns matched against the app of the node a device is linked to, which is its
+    /// `application.name`, `application.process.binary` or `node.name`, whichever is set first.
+    pub apps: Vec<String>,
+    /// Desktop notifications for links in this scope, needs a name.
+    #[serde(default)]
+    pub notification: NotificationTemplate,
+}
+
+/// Decides about links by their own properties, before looking at the nodes they connect.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkRule {
+    /// Patterns keyed by link property, all of them have to match.
+    pub properties: BTreeMap<String, String>,
+    pub action: LinkRuleAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkRuleAction {
+    /// Never count the link.
+    Ignore,
+    /// Count the link even if neither of its nodes is in scope.
+    Scope,
+}
+
+/// Whether links to nodes without any app information count for app specific scopes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownAppPolicy {
+    Allow,
+    #[default]
+    Deny,
+}
+
+/// Drives the on air state from a moving average of link presence instead of the links
+/// directly, so short gaps and bursts are smoothed out.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmoothingConfig {
+    /// Weight of the current link presence in every 50ms update, between 0 and 1.
+    pub factor: f64,
+    /// Level the average has to reach to be on air.
+    #[serde(default = "default_smoothing_threshold")]
+    pub threshold: f64,
+}
+
+fn default_smoothing_threshold() -> f64 {
+    0.5
+}
+
+/// Reminds of being on air for a long time, e.g. in a meeting that ran over.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WarningConfig {
+    /// Continuous time on air before the warning hooks run.
+    #[serde(with = "humantime_serde")]
+    pub after: Duration,
+    /// Warn again every time the same duration has passed.
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+/// Range of active links a device is expected to have, outside of it the fan out alarm hooks
+/// run. Too few links only count while on air.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FanOutConfig {
+    pub min: Option<usize>,
+    pub max: Option<usize>,
+}
+
+impl FanOutConfig {
+    pub fn contains(&self, links: usize) -> bool {
+        self.min.is_none_or(|min| links >= min) && self.max.is_none_or(|max| links <= max)
+    }
+}
+
+impl fmt::Display for FanOutConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) if min == max => write!(f, "exactly {}", min),
+            (Some(min), Some(max)) => write!(f, "{} to {}", min, max),
+            (Some(min), None) => write!(f, "at least {}", min),
+            (None, Some(max)) => write!(f, "at most {}", max),
+            (None, None) => write!(f, "any number of"),
+        }
+    }
+}
+
+/// What `min_active_links` counts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkCountScope {
+    /// All in scope links, whichever devices they come from.
+    #[default]
+    Total,
+    /// Links of the device with the most links, so a single device needs that many.
+    PerDevice,
+    /// Distinct devices with at least one link.
+    Devices,
+}
+
+/// How the in scope devices drive the actors.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TallyMode {
+    /// One tally that is on air while any in scope device is, all actors follow it.
+    #[default]
+    Combined,
+    /// Routes go on and off air with their own devices while the tally is on air.
+    PerDevice,
+}
+
+/// What to do about links whose node ids can't be parsed, e.g. port level links.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MalformedLinkPolicy {
+    /// Only log them at debug level.
+    Skip,
+    /// Warn the first time a kind of malformed link is seen, log repeats at debug level.
+    #[default]
+    WarnOnce,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            devices_in_scope: HashSet::from([
+                "Built-in Audio Analog Stereo".to_string(),
+                "M300-XT v1.06".to_string(),
+            ]),
+            devices_ignored: HashSet::from(["PulseAudio Volume Control".to_string()]),
+            devices_in_scope_file: None,
+            devices_ignored_file: None,
+            roles_in_scope: HashSet::new(),
+            scope_all_sources: false,
+            scope_default_source: false,
+            include_video: false,
+            ignore_monitors: true,
+            require_source_class: true,
+            count_playback_links: false,
+            device_priority: Vec::new(),
+            on_air_cmd: None,
+            off_air_cmd: None,
+            warning_cmd: None,
+            fan_out_cmd: None,
+            on_air_exec: None,
+            off_air_exec: None,
+            on_air_script: None,
+            off_air_script: None,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            malformed_links: MalformedLinkPolicy::default(),
+            on_air_delay: Duration::ZERO,
+            min_on_dwell: Duration::ZERO,
+            min_off_dwell: Duration::ZERO,
+            coalesce_window: Duration::ZERO,
+            active_hours: Vec::new(),
+            startup_delay: Duration::ZERO,
+            empty_graph_retry: None,
+            presence_timeout: None,
+            webhook: None,
+            light: None,
+            meeting_mode: None,
+            notification: NotificationConfig::default(),
+            #[cfg(feature = "obs")]
+            obs: None,
+            #[cfg(feature = "serial")]
+            serial: None,
+            #[cfg(feature = "ble")]
+            ble: None,
+            emit_events: false,
+            fifo: None,
+            #[cfg(feature = "idle-inhibit")]
+            idle_inhibit: false,
+            #[cfg(feature = "x11")]
+            x11_property: false,
+            require_active_links: false,
+            require_live_streams: false,
+            require_audio_ports: false,
+            invert: false,
+            run_off_air_on_shutdown: true,
+            smoothing: None,
+            min_active_links: 1,
+            link_count_scope: LinkCountScope::default(),
+            min_link_age: Duration::ZERO,
+            on_air_warning: None,
+            fan_out: BTreeMap::new(),
+            actor_priority: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            scopes: Vec::new(),
+            link_rules: Vec::new(),
+            routes: Vec::new(),
+            tally: TallyMode::default(),
+            unknown_app_policy: UnknownAppPolicy::default(),
+            history_size: 50,
+            session_summary: true,
+            control_socket: None,
+            control_same_user_only: true,
+            flag_file: None,
+            http: None,
+            remote: None,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the effective config: values from the command line take precedence over
+    /// environment variables, which take precedence over the config files.
+    pub fn load(cli: &Cli) -> Result<Self, Error> {
+        Sources::load(cli)?.config()
+    }
+
+    /// Entries of the list files add to the lists from the config files, the environment and
+    /// command line still replace them.
+    fn read_list_files(&mut self) -> Result<(), Error> {
+        if let Some(path) = &self.devices_in_scope_file {
+            self.devices_in_scope.extend(read_list(path)?);
+        }
+        if let Some(path) = &self.devices_ignored_file {
+            self.devices_ignored.extend(read_list(path)?);
+        }
+        Ok(())
+    }
+
+    /// `%label%` used to be replaced within shell commands, which let device names inject code.
+    /// Rejecting it keeps old configs from passing the placeholder to the shell verbatim.
+    fn check_label_placeholders(&self) -> Result<(), Error> {
+        let commands = [
+            ("on_air_cmd", &self.on_air_cmd),
+            ("off_air_cmd", &self.off_air_cmd),
+            ("warning_cmd", &self.warning_cmd),
+            ("fan_out_cmd", &self.fan_out_cmd),
+        ];
+        let routes = self.routes.iter().flat_map(|route| {
+            [
+                ("routes.on_air_cmd", &route.on_air_cmd),
+                ("routes.off_air_cmd", &route.off_air_cmd),
+            ]
+        });
+        for (key, command) in commands.into_iter().chain(routes) {
+            let placeholder = command.as_ref().is_some_and(|command| command.contains("%label%"));
+            ensure!(!placeholder, LabelPlaceholderSnafu { key });
+        }
+        Ok(())
+    }
+
+    fn apply_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) {
+        for (key, value) in vars {
+            match key.as_str() {
+                ENV_DEVICES_IN_SCOPE => self.devices_in_scope = split_list(&value),
+                ENV_DEVICES_IGNORED => self.devices_ignored = split_list(&value),
+                ENV_ON_AIR_CMD => self.on_air_cmd = Some(value),
+                ENV_OFF_AIR_CMD => self.off_air_cmd = Some(value),
+                ENV_REMOTE => self.remote = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if !cli.devices_in_scope.is_empty() {
+            self.devices_in_scope = cli.devices_in_scope.iter().cloned().collect();
+        }
+        if !cli.devices_ignored.is_empty() {
+            self.devices_ignored = cli.devices_ignored.iter().cloned().collect();
+        }
+        if cli.on_air_cmd.is_some() {
+            self.on_air_cmd = cli.on_air_cmd.clone();
+        }
+        if cli.off_air_cmd.is_some() {
+            self.off_air_cmd = cli.off_air_cmd.clone();
+        }
+        if cli.emit_events {
+            self.emit_events = true;
+        }
+        if cli.fifo.is_some() {
+            self.fifo = cli.fifo.clone();
+        }
+        if let Some(startup_delay) = cli.startup_delay {
+            self.startup_delay = startup_delay;
+        }
+        if cli.control_socket.is_some() {
+            self.control_socket = cli.control_socket.clone();
+        }
+        if cli.remote.is_some() {
+            self.remote = cli.remote.clone();
+        }
+    }
+}
+
+/// What [`Config::load`] builds the config from, kept to build it again at runtime, e.g. with
+/// a snippet from the control socket merged over the config files.
+#[derive(Debug, Clone)]
+pub struct Sources {
+    /// The merged profile and config files.
+    files: toml::Table,
+    cli: Cli,
+}
+
+impl Sources {
+    /// Reads the profile, the config file and the snippets in `conf.d` next to it.
+    pub fn load(cli: &Cli) -> Result<Self, Error> {
+        let path = cli.config.clone().or_else(default_config_path);
+        let mut table = toml::Table::new();
+        if let Some(name) = &cli.profile {
+            merge(&mut table, profile(name)?);
+        }
+        match &path {
+            Some(path) if path.as_os_str() == STDIN_CONFIG => {
+                merge(&mut table, read_stdin_table(path)?);
+            }
+            Some(path) => {
+                if cli.config.is_some() || path.exists() {
+                    merge(&mut table, read_table(path)?);
+                }
+                if let Some(dir) = path.parent().map(|parent| parent.join("conf.d")) {
+                    for snippet in config_snippets(&dir)? {
+                        merge(&mut table, read_table(&snippet)?);
+                    }
+                }
+            }
+            None => {}
+        }
+        Ok(Sources {
+            files: table,
+            cli: cli.clone(),
+        })
+    }
+
+    /// Builds the config, see [`Config::load`]. List files are read again every time.
+    pub fn config(&self) -> Result<Config, Error> {
+        build(self.files.clone(), &self.cli)
+    }
+
+    /// Like [`Self::config`], with the snippet merged over the config files as if it was the
+    /// last file in `conf.d`.
+    pub fn with_snippet(&self, snippet: &str) -> Result<Config, Error> {
+        toml::from_str::<Config>(snippet).context(ParseSnippetSnafu)?;
+        let mut files = self.files.clone();
+        merge(&mut files, toml::from_str(snippet).context(ParseSnippetSnafu)?);
+        build(files, &self.cli)
+    }
+}
+
+/// Applies the list files, the environment and the command line to the merged config files.
+fn build(files: toml::Table, cli: &Cli) -> Result<Config, Error> {
+    let mut config: Config = files.try_into().context(InvalidConfigSnafu)?;
+    config.read_list_files()?;
+    config.apply_env(env::vars());
+    config.apply_cli(cli);
+    config.check_label_placeholders()?;
+    Ok(config)
+}
+
+fn profile(name: &str) -> Result<toml::Table, Error> {
+    let (_, profile) = PROFILES
+        .iter()
+        .find(|(profile, _)| *profile == name)
+        .with_context(|| UnknownProfileSnafu {
+            name,
+            available: PROFILES
+                .iter()
+                .map(|(profile, _)| *profile)
+                .collect::<Vec<_>>()
+                .join(", "),
+        })?;
+    debug!("Applying profile [{}]", name);
+    Ok(toml::from_str(profile).expect("built-in profiles are valid"))
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join(crate::APP_NAME).join("config.toml"))
+}
+
+fn read_table(path: &Path) -> Result<toml::Table, Error> {
+    debug!("Loading config from [{}]", path.display());
+    let contents = fs::read_to_string(path).context(ReadConfigSnafu { path })?;
+    let mut table = parse_table(&contents, path)?;
+    resolve_list_files(&mut table, path);
+    Ok(table)
+}
+
+/// Needs to happen per file, as merging loses track of which file set a path.
+fn resolve_list_files(table: &mut toml::Table, config_path: &Path) {
+    let Some(dir) = config_path.parent() else {
+        return;
+    };
+    for key in LIST_FILE_KEYS {
+        if let Some(toml::Value::String(file)) = table.get_mut(key) {
+            if Path::new(file.as_str()).is_relative() {
+                *file = dir.join(file.as_str()).to_string_lossy().into_owned();
+            }
+        }
+    }
+}
+
+/// One entry per line, blank lines and lines starting with `#` are skipped.
+fn read_list(path: &Path) -> Result<Vec<String>, Error> {
+    debug!("Loading list from [{}]", path.display());
+    let contents = fs::read_to_string(path).context(ReadListFileSnafu { path })?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Reads all of stdin before returning, so nothing is left for later once connected.
+fn read_stdin_table(path: &Path) -> Result<toml::Table, Error> {
+    debug!("Loading config from stdin");
+    let mut contents = String::new();
+    io::stdin()
+        .read_to_string(&mut contents)
+        .context(ReadConfigSnafu { path })?;
+    parse_table(&contents, path)
+}
+
+fn parse_table(contents: &str, path: &Path) -> Result<toml::Table, Error> {
+    // check each file on its own, so mistakes are reported with their file and line
+    toml::from_str::<Config>(contents).context(ParseConfigSnafu { path })?;
+    toml::from_str(contents).context(ParseConfigSnafu { path })
+}
+
+/// All `*.toml` files in the directory in lexical order, none if it doesn't exist.
+fn config_snippets(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut snippets = Vec::new();
+    for entry in fs::read_dir(dir).context(ReadConfigDirSnafu { path: dir })? {
+        let path = entry.context(ReadConfigDirSnafu { path: dir })?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+            && path.is_file()
+        {
+            snippets.push(path);
+        }
+    }
+    snippets.sort();
+    Ok(snippets)
+}
+
+/// Tables are merged recursively, arrays are appended to and all other values are replaced.
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (Some(toml::Value::Array(base)), toml::Value::Array(overlay)) => base.extend(overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn split_list(value: &str) -> HashSet<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn sources(files: &str) -> Sources {
+        Sources {
+            files: toml::from_str(files).unwrap(),
+            cli: Cli::parse_from([crate::APP_NAME]),
+        }
+    }
+
+    #[test]
+    fn snippets_are_merged_over_the_config_files() {
+        let sources = sources(
+            "command_timeout = \"3s\"\ntally = \"per_device\"\n[actor_priority]\nwebhook = 10",
+        );
+        let config = sources.with_snippet("on_air_cmd = \"true\"").unwrap();
+        assert_eq!(config.command_timeout, Duration::from_secs(3));
+        assert_eq!(config.tally, TallyMode::PerDevice);
+        assert_eq!(config.actor_priority.get("webhook"), Some(&10));
+        assert_eq!(config.on_air_cmd.as_deref(), Some("true"));
+        assert!(sources.with_snippet("on_air_cmd = 1").is_err());
+        assert!(sources.with_snippet("on_air_command = \"true\"").is_err());
+    }
+
+    /// A fresh directory with the given files, which may be in subdirectories.
+    fn config_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("onair-buddy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn load(dir: &Path) -> Result<Config, Error> {
+        let config = dir.join("config.toml");
+        Config::load(&Cli::parse_from([crate::APP_NAME, "--config", config.to_str().unwrap()]))
+    }
+
+    #[test]
+    fn the_command_line_overrides_the_environment_which_overrides_the_files() {
+        let mut config: Config =
+            toml::from_str("on_air_cmd = \"file\"\noff_air_cmd = \"file\"\nremote = \"file\"")
+                .unwrap();
+        config.apply_env([
+            (ENV_ON_AIR_CMD.to_string(), "env".to_string()),
+            (ENV_OFF_AIR_CMD.to_string(), "env".to_string()),
+        ]);
+        config.apply_cli(&Cli::parse_from([crate::APP_NAME, "--on-air-cmd", "cli"]));
+        assert_eq!(config.on_air_cmd.as_deref(), Some("cli"));
+        assert_eq!(config.off_air_cmd.as_deref(), Some("env"));
+        assert_eq!(config.remote.as_deref(), Some("file"));
+    }
+
+    #[test]
+    fn snippets_are_merged_in_lexical_order() {
+        let dir = config_dir(
+            "conf-d",
+            &[
+                ("config.toml", "on_air_cmd = \"main\"\ndevice_priority = [\"main\"]"),
+                ("conf.d/20-b.toml", "on_air_cmd = \"b\"\ndevice_priority = [\"b\"]"),
+                ("conf.d/10-a.toml", "on_air_cmd = \"a\"\ndevice_priority = [\"a\"]"),
+                ("conf.d/30-notes.txt", "not toml"),
+            ],
+        );
+        let config = load(&dir).unwrap();
+        assert_eq!(config.on_air_cmd.as_deref(), Some("b"));
+        assert_eq!(config.device_priority, ["main", "a", "b"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_their_file() {
+        let dir = config_dir(
+            "unknown-key",
+            &[("config.toml", ""), ("conf.d/10-typo.toml", "on_air_command = \"true\"")],
+        );
+        let error = load(&dir).unwrap_err().to_string();
+        assert!(error.contains("10-typo.toml"), "{}", error);
+        assert!(error.contains("on_air_command"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn list_files_are_relative_to_the_config_setting_them() {
+        let dir = config_dir(
+            "list-files",
+            &[
+                ("config.toml", ""),
+                ("conf.d/10-mics.toml", "devices_in_scope_file = \"mics.txt\""),
+                ("conf.d/mics.txt", "# headsets\nHeadset\n\n  Desk Mic  \n"),
+            ],
+        );
+        let config = load(&dir).unwrap();
+        assert_eq!(config.devices_in_scope_file, Some(dir.join("conf.d/mics.txt")));
+        assert!(config.devices_in_scope.contains("Headset"));
+        assert!(config.devices_in_scope.contains("Desk Mic"));
+        assert!(!config.devices_in_scope.contains("# headsets"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn label_placeholders_in_shell_commands_are_rejected() {
+        let sources = sources("[labels]\n\"Mic\" = \"Podcast Mic\"");
+        let error = sources
+            .with_snippet("[[routes]]\ndevices = [\"Mic\"]\non_air_cmd = \"echo %label%\"")
+            .unwrap_err();
+        assert!(error.to_string().contains("routes.on_air_cmd"), "{}", error);
+        assert!(sources.with_snippet("on_air_cmd = 'echo \"$ONAIR_LABEL\"'").is_ok());
+    }
+}
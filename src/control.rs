@@ -0,0 +1,378 @@
+//! Unix socket for controlling a running instance.
+//!
+//! Every connection carries a single command on its first line, optionally followed by a
+//! payload, and is answered with `ok` or `error: <reason>`:
+//!
+//! ```text
+//! pause
+//! resume
+//! quit
+//! reset
+//! reload
+//! force-link add <device>
+//! force-link remove [<device>]
+//! actors
+//! on_air_cmd = "notify-send 'testing'"
+//! ```
+//!
+//! `actors` replaces all configured actors with the ones of the active config, with the config
+//! snippet that follows merged over it, `reload` reads the list files again and applies the
+//! resulting device lists. `status [json|plain|prom]` is answered with the status
+//! as a JSON object, a single `1` or `0`, or in the Prometheus text format instead, `links`
+//! with a line per active in scope link holding its id, device and target separated by tabs.
+//! `force-link` adds or removes made up active links for testing actors against a running
+//! instance.
+//!
+//! Connections starting with `?` get a single `1` or `0` back for on or off air, without
+//! waiting for the end of the line, so status bars can poll cheaply.
+use crate::actors::{self, SwappableActor};
+use crate::config::Sources;
+use crate::recording_watcher::{StatusSource, WatcherCommand};
+use pipewire::channel::Sender;
+use snafu::prelude::*;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Connections that don't send their command in time are dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Control socket [{}] is already in use", path.display()))]
+    InUse { path: PathBuf },
+    #[snafu(display("Failed to bind control socket [{}]", path.display()))]
+    Bind {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("No control socket configured, set control_socket or --control-socket"))]
+    NoControlSocket,
+    #[snafu(display("Failed to query control socket [{}], is onair-buddy running?", path.display()))]
+    Query {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Removes the socket file again when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Accepts connections on a background thread. Commands for the watcher are forwarded
+    /// through `commands`, actor replacements are built from `sources` and applied to `actors`
+    /// directly.
+    pub fn spawn(
+        path: &Path,
+        commands: Sender<WatcherCommand>,
+        actors: Arc<SwappableActor>,
+        sources: Sources,
+        status: Arc<dyn StatusSource>,
+        same_user_only: bool,
+    ) -> Result<Self, Error> {
+        if path.exists() {
+            // a leftover from an instance that didn't shut down cleanly can be replaced
+            ensure!(UnixStream::connect(path).is_err(), InUseSnafu { path });
+            let _ = fs::remove_file(path);
+        }
+        let listener = UnixListener::bind(path).context(BindSnafu { path })?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).context(BindSnafu { path })?;
+        info!("Listening for commands on [{}]", path.display());
+        let sources = Arc::new(sources);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) if same_user_only && !peer_allowed(&stream) => {
+                        let _ = (&stream).write_all(b"error: permission denied\n");
+                    }
+                    Ok(stream) => {
+                        // a client that is slow to send its command doesn't hold up the others
+                        let commands = commands.clone();
+                        let actors = actors.clone();
+                        let sources = sources.clone();
+                        let status = status.clone();
+                        thread::spawn(move || {
+                            handle(stream, &commands, &actors, &sources, status.as_ref())
+                        });
+                    }
+                    Err(e) => warn!("failed to accept control connection: {}", e),
+                }
+            }
+        });
+        Ok(ControlSocket {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+/// Only the user running onair-buddy and root may send commands. The socket file is only
+/// accessible by its owner as well, but this also covers connections made before its
+/// permissions were set, and sockets in directories with lax permissions.
+fn peer_allowed(stream: &UnixStream) -> bool {
+    // SAFETY: getuid can't fail
+    let own_uid = unsafe { libc::getuid() };
+    match peer_uid(stream) {
+        Ok(uid) if uid == own_uid || uid == 0 => true,
+        Ok(uid) => {
+            warn!("rejecting control connection from uid [{}]", uid);
+            false
+        }
+        Err(e) => {
+            warn!("rejecting control connection without credentials: {}", e);
+            false
+        }
+    }
+}
+
+/// Uid of the process on the other end, from `SO_PEERCRED`.
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: the pointers are valid for writes of the given length for the whole call
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut credentials as *mut libc::ucred).cast(),
+            &mut length,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(credentials.uid)
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn handle(
+    stream: UnixStream,
+    commands: &Sender<WatcherCommand>,
+    actors: &SwappableActor,
+    sources: &Sources,
+    status: &dyn StatusSource,
+) {
+    if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+        debug!("failed to set a timeout on control connection: {}", e);
+        return;
+    }
+    let mut reader = BufReader::new(&stream);
+    let reply = match reader.fill_buf() {
+        Ok([b'?', ..]) => if status.status().on_air { "1" } else { "0" }.to_string(),
+        Ok(_) => {
+            let mut command = String::new();
+            let mut payload = String::new();
+            let result = reader
+                .read_line(&mut command)
+                .map_err(|e| e.to_string())
+                .and_then(|_| match command.trim() {
+                    "pause" => send(commands, WatcherCommand::Pause),
+                    "resume" => send(commands, WatcherCommand::Resume),
+                    "quit" => send(commands, WatcherCommand::Quit),
+                    "reset" => send(commands, WatcherCommand::Reset),
+                    "reload" => reload(commands, sources),
+                    line if line.starts_with("status") => status_reply(status, line),
+                    line if line.starts_with("force-link") => force_link(commands, line),
+                    "links" => Ok(links(status)),
+                    "actors" => reader
+                        .read_to_string(&mut payload)
+                        .map_err(|e| e.to_string())
+                        .and_then(|_| replace_actors(commands, actors, sources, &payload)),
+                    other => Err(format!("unknown command [{}]", other)),
+                });
+            debug!("control command [{}]: {:?}", command.trim(), result);
+            match result {
+                Ok(reply) if reply.is_empty() => reply,
+                Ok(reply) => format!("{}\n", reply),
+                Err(e) => format!("error: {}\n", e),
+            }
+        }
+        Err(e) => {
+            debug!("failed to read from control connection: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = (&stream).write_all(reply.as_bytes()) {
+        debug!("failed to reply to control connection: {}", e);
+    }
+}
+
+/// `status` followed by an optional format, JSON if none is given.
+fn status_reply(status: &dyn StatusSource, line: &str) -> Result<String, String> {
+    match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["status"] | ["status", "json"] => Ok(status.status().to_json()),
+        ["status", "plain"] => Ok(if status.status().on_air { "1" } else { "0" }.to_string()),
+        ["status", "prom"] => Ok(status.status().to_prometheus()),
+        _ => Err(format!("unknown status format in [{}], expected json, plain or prom", line)),
+    }
+}
+
+fn links(status: &dyn StatusSource) -> String {
+    let field = |value: &str| value.replace(['\t', '\n'], " ");
+    status
+        .status()
+        .links
+        .iter()
+        .map(|link| {
+            format!(
+                "{}\t{}\t{}\t{}",
+                link.id,
+                field(&link.device),
+                field(&link.target),
+                field(link.format.as_deref().unwrap_or_default())
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Client side of `--status`: prints the state of the running instance and its active links.
+pub fn print_status(path: Option<&Path>) -> Result<(), Error> {
+    let path = path.context(NoControlSocketSnafu)?;
+    let on_air = query(path, "?")? == "1";
+    let links = query(path, "links\n")?;
+    println!("{}", if on_air { "On air" } else { "Off air" });
+    let rows: Vec<Vec<&str>> = links
+        .lines()
+        .map(|line| line.splitn(4, '\t').collect())
+        .collect();
+    if rows.is_empty() {
+        return Ok(());
+    }
+    let header = ["LINK", "DEVICE", "TARGET", "FORMAT"];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|value| value.chars().count())
+                .chain([header[column].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    for row in [header.to_vec()].iter().chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+    Ok(())
+}
+
+fn query(path: &Path, request: &str) -> Result<String, Error> {
+    let mut stream = UnixStream::connect(path).context(QuerySnafu { path })?;
+    stream
+        .write_all(request.as_bytes())
+        .context(QuerySnafu { path })?;
+    stream
+        .shutdown(std::net::Shutdown::Write)
+        .context(QuerySnafu { path })?;
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .context(QuerySnafu { path })?;
+    Ok(reply)
+}
+
+fn send(commands: &Sender<WatcherCommand>, command: WatcherCommand) -> Result<String, String> {
+    commands
+        .send(command)
+        .map(|_| "ok".to_string())
+        .map_err(|_| "watcher is not running".to_string())
+}
+
+fn force_link(commands: &Sender<WatcherCommand>, line: &str) -> Result<String, String> {
+    let args = line["force-link".len()..].trim();
+    let (action, device) = args.split_once(' ').unwrap_or((args, ""));
+    let device = device.trim();
+    match action {
+        "add" if !device.is_empty() => {
+            send(commands, WatcherCommand::ForceLink(device.to_string()))
+        }
+        "add" => Err("force-link add needs a device name".to_string()),
+        "remove" => send(
+            commands,
+            WatcherCommand::ClearForcedLinks(Some(device.to_string()).filter(|d| !d.is_empty())),
+        ),
+        other => Err(format!("unknown force-link action [{}]", other)),
+    }
+}
+
+/// Only the device lists are applied, the config files were read at startup.
+fn reload(commands: &Sender<WatcherCommand>, sources: &Sources) -> Result<String, String> {
+    let config = sources.config().map_err(|e| e.to_string())?;
+    info!("Reloading list files");
+    send(
+        commands,
+        WatcherCommand::SetDeviceLists {
+            devices_in_scope: config.devices_in_scope,
+            devices_ignored: config.devices_ignored,
+        },
+    )
+}
+
+/// The new actors are told about the current state right away, see [`WatcherCommand::Replay`].
+fn replace_actors(
+    commands: &Sender<WatcherCommand>,
+    actors: &SwappableActor,
+    sources: &Sources,
+    snippet: &str,
+) -> Result<String, String> {
+    let config = sources.with_snippet(snippet).map_err(|e| e.to_string())?;
+    let actor = actors::from_config(&config).map_err(|e| e.to_string())?;
+    info!("Replacing actors");
+    actors.replace(actor);
+    send(commands, WatcherCommand::Replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::CompositeActor;
+    use crate::cli::Cli;
+    use crate::recording_watcher::Status;
+    use clap::Parser;
+
+    struct OffAir;
+
+    impl StatusSource for OffAir {
+        fn status(&self) -> Status {
+            Status::default()
+        }
+    }
+
+    #[test]
+    fn a_silent_client_does_not_block_others() {
+        let path = std::env::temp_dir().join(format!("onair-buddy-{}.sock", std::process::id()));
+        let (commands, _receiver) = pipewire::channel::channel();
+        let actors = Arc::new(SwappableActor::new(Box::new(CompositeActor::new(Vec::new()))));
+        let cli = Cli::parse_from(["onair-buddy", "--config", "/dev/null"]);
+        let sources = Sources::load(&cli).unwrap();
+        let status = Arc::new(OffAir);
+        let _socket = ControlSocket::spawn(&path, commands, actors, sources, status, true).unwrap();
+        let _silent = UnixStream::connect(&path).unwrap();
+        assert_eq!(query(&path, "?").unwrap(), "0");
+        assert_eq!(query(&path, "status plain").unwrap(), "0\n");
+    }
+}
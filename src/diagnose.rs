@@ -0,0 +1,51 @@
+use crate::config::Config;
+use crate::recording_watcher;
+use pipewire::types::ObjectType;
+use pipewire::{Context, MainLoop};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Connects once, waits for the initial registry enumeration and prints the details
+/// usually needed in bug reports.
+pub fn run(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mainloop = MainLoop::new()?;
+    let context = Context::new(&mainloop)?;
+    let core = recording_watcher::connect_core(&context, config.remote.as_deref())?;
+    let registry = core.get_registry()?;
+
+    let core_info: Rc<RefCell<Option<(String, String)>>> = Rc::default();
+    let nodes = Rc::new(Cell::new(0));
+    let links = Rc::new(Cell::new(0));
+
+    let registry_nodes = nodes.clone();
+    let registry_links = links.clone();
+    let _registry_listener = registry
+        .add_listener_local()
+        .global(move |global| match global.type_ {
+            ObjectType::Node => registry_nodes.set(registry_nodes.get() + 1),
+            ObjectType::Link => registry_links.set(registry_links.get() + 1),
+            _ => {}
+        })
+        .register();
+
+    let info = core_info.clone();
+    let _core_listener = core
+        .add_listener_local()
+        .info(move |core| {
+            *info.borrow_mut() = Some((core.name().to_string(), core.version().to_string()));
+        })
+        .register();
+    recording_watcher::run_until_enumerated(&mainloop, &core)?;
+
+    let remote = config.remote.as_deref().unwrap_or("default");
+    let (core_name, core_version) = core_info
+        .take()
+        .unwrap_or_else(|| ("unknown".to_string(), "unknown".to_string()));
+    println!("{} {}", crate::APP_NAME, env!("CARGO_PKG_VERSION"));
+    println!("remote:       {}", remote);
+    println!("core:         {}", core_name);
+    println!("core version: {}", core_version);
+    println!("nodes:        {}", nodes.get());
+    println!("links:        {}", links.get());
+    Ok(())
+}
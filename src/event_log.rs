@@ -0,0 +1,295 @@
+//! Recording of registry events for bug reports, and replaying them later.
+//!
+//! `--record-events <FILE>` writes every node, port, device and link PipeWire announces, every
+//! removal, every link state change, every update of watched node properties and every change
+//! of the default source to a file, one event per line with tab separated fields, shown as
+//! spaces here:
+//!
+//! ```text
+//! # onair-buddy event log 2
+//! 0  global  40  Node  node.name=alsa_input.pci-0000_00_1f.3  media.class=Audio/Source
+//! 3  enumerated
+//! 4  default-source  alsa_input.pci-0000_00_1f.3
+//! 12  global  100  Link  link.output.node=40  link.input.node=55
+//! 15  link-state  100  active
+//! 16  node-info  55  stream.is-live=true
+//! 3020  remove  100
+//! ```
+//!
+//! The first field is the time in milliseconds since recording started. Globals carry their
+//! id, type and all properties as `key=value`. `enumerated` marks the end of the initial
+//! graph, `default-source` without a name that there is no default source anymore. Default
+//! source changes are only recorded with `scope_default_source`. Backslashes, tabs and
+//! newlines within keys and values are escaped as `\\`, `\t` and `\n`. `--replay <FILE>`
+//! feeds such a file through the same state handling as a live session, with the original
+//! timing.
+use crate::recording_watcher::Props;
+use snafu::prelude::*;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const HEADER_PREFIX: &str = "# onair-buddy event log ";
+const VERSION: u32 = 2;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Failed to create event log [{}]", path.display()))]
+    Create {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Failed to read event log [{}]", path.display()))]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Not an onair-buddy event log, the first line should be [{}{}]",
+        HEADER_PREFIX, VERSION))]
+    MissingHeader,
+    #[snafu(display("Unsupported event log version [{}], expected {}", version, VERSION))]
+    UnsupportedVersion { version: String },
+    #[snafu(display("Invalid event in line {}: {}", line_number, reason))]
+    InvalidLine { line_number: usize, reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoggedEvent {
+    Global {
+        id: u32,
+        type_: String,
+        props: Vec<(String, String)>,
+    },
+    Remove {
+        id: u32,
+    },
+    LinkState {
+        id: u32,
+        active: bool,
+    },
+    NodeInfo {
+        id: u32,
+        props: Vec<(String, String)>,
+    },
+    /// PipeWire announced all objects that existed when connecting.
+    Enumerated,
+    DefaultSource {
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedLoggedEvent {
+    /// Time since the recording started.
+    pub at: Duration,
+    pub event: LoggedEvent,
+}
+
+/// Appends events to a file, flushing after every line so nothing is lost if the session
+/// ends abruptly.
+pub struct EventLog {
+    started: Instant,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl EventLog {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path).context(CreateSnafu { path })?);
+        writeln!(writer, "{}{}", HEADER_PREFIX, VERSION).context(CreateSnafu { path })?;
+        Ok(EventLog {
+            started: Instant::now(),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    pub fn global(&self, id: u32, type_: &str, props: &impl Props) {
+        self.write(&format!("global\t{}\t{}{}", id, type_, format_props(props)));
+    }
+
+    pub fn remove(&self, id: u32) {
+        self.write(&format!("remove\t{}", id));
+    }
+
+    pub fn link_state(&self, id: u32, active: bool) {
+        let state = if active { "active" } else { "inactive" };
+        self.write(&format!("link-state\t{}\t{}", id, state));
+    }
+
+    pub fn node_info(&self, id: u32, props: &impl Props) {
+        self.write(&format!("node-info\t{}{}", id, format_props(props)));
+    }
+
+    pub fn enumerated(&self) {
+        self.write("enumerated");
+    }
+
+    pub fn default_source(&self, name: Option<&str>) {
+        match name {
+            Some(name) => self.write(&format!("default-source\t{}", escape(name))),
+            None => self.write("default-source"),
+        }
+    }
+
+    fn write(&self, event: &str) {
+        let millis = self.started.elapsed().as_millis();
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writeln!(writer, "{}\t{}", millis, event).and_then(|_| writer.flush()) {
+            warn!("failed to record event: {}", e);
+        }
+    }
+}
+
+fn format_props(props: &impl Props) -> String {
+    props
+        .iter()
+        .map(|(key, value)| format!("\t{}={}", escape(key), escape(value)))
+        .collect()
+}
+
+pub fn load(path: &Path) -> Result<Vec<TimedLoggedEvent>, Error> {
+    let log = fs::read_to_string(path).context(ReadSnafu { path })?;
+    check_version(&log)?;
+    parse(&log)
+}
+
+/// Recordings of other versions may lack events the replay relies on.
+fn check_version(log: &str) -> Result<(), Error> {
+    let version = log
+        .lines()
+        .next()
+        .and_then(|header| header.strip_prefix(HEADER_PREFIX))
+        .context(MissingHeaderSnafu)?;
+    ensure!(
+        version.trim() == VERSION.to_string(),
+        UnsupportedVersionSnafu { version }
+    );
+    Ok(())
+}
+
+pub fn parse(log: &str) -> Result<Vec<TimedLoggedEvent>, Error> {
+    log.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            parse_line(line).map_err(|reason| Error::InvalidLine {
+                line_number: index + 1,
+                reason,
+            })
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Result<TimedLoggedEvent, String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let (at, event) = fields
+        .split_first()
+        .ok_or_else(|| "missing time".to_string())?;
+    let at = at
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("invalid time [{}]", at))?;
+    let event = match event {
+        ["global", id, type_, props @ ..] => LoggedEvent::Global {
+            id: parse_id(id)?,
+            type_: type_.to_string(),
+            props: parse_props(props)?,
+        },
+        ["remove", id] => LoggedEvent::Remove { id: parse_id(id)? },
+        ["link-state", id, state] => LoggedEvent::LinkState {
+            id: parse_id(id)?,
+            active: match *state {
+                "active" => true,
+                "inactive" => false,
+                _ => return Err(format!("unknown link state [{}]", state)),
+            },
+        },
+        ["node-info", id, props @ ..] => LoggedEvent::NodeInfo {
+            id: parse_id(id)?,
+            props: parse_props(props)?,
+        },
+        ["enumerated"] => LoggedEvent::Enumerated,
+        ["default-source"] => LoggedEvent::DefaultSource { name: None },
+        ["default-source", name] => LoggedEvent::DefaultSource {
+            name: Some(unescape(name)),
+        },
+        _ => return Err(format!("unknown event [{}]", event.join(" "))),
+    };
+    Ok(TimedLoggedEvent { at, event })
+}
+
+fn parse_props(props: &[&str]) -> Result<Vec<(String, String)>, String> {
+    props
+        .iter()
+        .map(|prop| {
+            prop.split_once('=')
+                .map(|(key, value)| (unescape(key), unescape(value)))
+                .ok_or_else(|| format!("expected key=value, got [{}]", prop))
+        })
+        .collect()
+}
+
+fn parse_id(id: &str) -> Result<u32, String> {
+    id.parse().map_err(|_| format!("invalid id [{}]", id))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_header_version_is_checked() {
+        assert!(matches!(check_version("0\tremove\t1\n"), Err(Error::MissingHeader)));
+        assert!(matches!(
+            check_version("# onair-buddy event log 1\n0\tremove\t1\n"),
+            Err(Error::UnsupportedVersion { version }) if version == "1"
+        ));
+        assert!(check_version("# onair-buddy event log 2\n0\tremove\t1\n").is_ok());
+    }
+
+    #[test]
+    fn enumeration_and_default_source_changes_are_parsed() {
+        let log = "# onair-buddy event log 2\n\
+                   3\tenumerated\n\
+                   4\tdefault-source\tmic\\twith tab\n\
+                   5\tdefault-source\n";
+        let events: Vec<LoggedEvent> = parse(log).unwrap().into_iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            [
+                LoggedEvent::Enumerated,
+                LoggedEvent::DefaultSource {
+                    name: Some("mic\twith tab".to_string())
+                },
+                LoggedEvent::DefaultSource { name: None },
+            ]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Flag file that forces the on air state, for tools that can't talk to the control socket.
+//!
+//! While the file contains `1` the watcher is on air regardless of the links, any other
+//! content or a missing file leaves the decision to the links. Changes are picked up through
+//! inotify on the containing directory, so the file may be created, replaced or removed at
+//! any time.
+use crate::recording_watcher::WatcherCommand;
+use pipewire::channel::Sender;
+use snafu::prelude::*;
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use tracing::{info, warn};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Flag file [{}] has no file name", path.display()))]
+    NoFileName { path: PathBuf },
+    #[snafu(display("Failed to watch the directory of flag file [{}]", path.display()))]
+    Watch { path: PathBuf, source: io::Error },
+}
+
+/// Events that may change what reading the file returns.
+const WATCHED_EVENTS: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+/// Watches the file on a background thread and forwards changes of its state through
+/// `commands`. The directory it is in has to exist already.
+pub fn watch(path: &Path, commands: Sender<WatcherCommand>) -> Result<(), Error> {
+    let name = path
+        .file_name()
+        .context(NoFileNameSnafu { path })?
+        .to_owned();
+    let directory = path
+        .parent()
+        .filter(|directory| !directory.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut inotify = inotify_watch(directory).context(WatchSnafu { path })?;
+
+    let path = path.to_path_buf();
+    let mut forced = read_flag(&path);
+    info!(
+        "Watching flag file [{}], currently forcing on air [{}]",
+        path.display(),
+        forced
+    );
+    if forced && commands.send(WatcherCommand::SetForced(true)).is_err() {
+        return Ok(());
+    }
+    thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let read = match inotify.read(&mut buffer) {
+                Ok(read) => read,
+                Err(e) => {
+                    warn!("Stopped watching flag file [{}]: {}", path.display(), e);
+                    return;
+                }
+            };
+            if !concerns(&buffer[..read], &name) {
+                continue;
+            }
+            let now = read_flag(&path);
+            if now != forced {
+                forced = now;
+                info!("Flag file [{}] forces on air [{}]", path.display(), forced);
+                if commands.send(WatcherCommand::SetForced(forced)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+fn inotify_watch(directory: &Path) -> io::Result<File> {
+    let directory = CString::new(directory.as_os_str().as_bytes())?;
+    // SAFETY: inotify_init1 has no memory safety requirements
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a freshly created descriptor nothing else owns
+    let inotify = unsafe { File::from_raw_fd(fd) };
+    // SAFETY: directory is a valid NUL terminated string for the duration of the call
+    if unsafe { libc::inotify_add_watch(fd, directory.as_ptr(), WATCHED_EVENTS) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(inotify)
+}
+
+/// Whether any of the inotify events read into `events` is about the file called `name`.
+fn concerns(mut events: &[u8], name: &OsStr) -> bool {
+    let header = mem::size_of::<libc::inotify_event>();
+    while events.len() >= header {
+        // SAFETY: the kernel only returns whole events, the buffer just isn't aligned for them
+        let event: libc::inotify_event =
+            unsafe { std::ptr::read_unaligned(events.as_ptr().cast()) };
+        let end = (header + event.len as usize).min(events.len());
+        // the name is padded with NULs
+        let event_name = &events[header..end];
+        let event_name = event_name
+            .split(|byte| *byte == 0)
+            .next()
+            .unwrap_or_default();
+        if event_name == name.as_bytes() {
+            return true;
+        }
+        events = &events[end..];
+    }
+    false
+}
+
+/// Missing or unreadable files don't force anything.
+fn read_flag(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|content| content.trim() == "1")
+}
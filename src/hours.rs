@@ -0,0 +1,181 @@
+//! Time windows for `active_hours`, like `09:00-18:00` or `mon-fri 09:00-17:30`.
+use serde::Deserialize;
+use std::mem;
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Times are minutes since midnight, windows ending before they start span midnight and
+/// belong to the day they start on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HoursWindow {
+    /// Bit per weekday, starting with Monday.
+    days: u8,
+    start: u16,
+    end: u16,
+}
+
+impl HoursWindow {
+    fn contains(&self, time: LocalTime) -> bool {
+        let on = |weekday: u8| self.days & (1 << weekday) != 0;
+        if self.start < self.end {
+            on(time.weekday) && (self.start..self.end).contains(&time.minute)
+        } else {
+            let yesterday = (time.weekday + 6) % 7;
+            (on(time.weekday) && time.minute >= self.start)
+                || (on(yesterday) && time.minute < self.end)
+        }
+    }
+}
+
+impl TryFrom<String> for HoursWindow {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || {
+            format!(
+                "invalid active hours [{}], expected e.g. [09:00-18:00] or [mon-fri 09:00-18:00]",
+                value
+            )
+        };
+        let (days, times) = match value.trim().split_once(' ') {
+            Some((days, times)) => (parse_days(days.trim()).ok_or_else(invalid)?, times.trim()),
+            None => (0b111_1111, value.trim()),
+        };
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let start = parse_time(start).filter(|start| *start < 24 * 60).ok_or_else(invalid)?;
+        let end = parse_time(end).ok_or_else(invalid)?;
+        if start == end {
+            return Err(format!(
+                "invalid active hours [{}], the window is empty, use [00:00-24:00] for whole days",
+                value
+            ));
+        }
+        Ok(HoursWindow { days, start, end })
+    }
+}
+
+/// Days like `mon`, `mon-fri` or `sat,sun`.
+fn parse_days(days: &str) -> Option<u8> {
+    let day = |name: &str| {
+        WEEKDAYS
+            .iter()
+            .position(|day| name.eq_ignore_ascii_case(day))
+    };
+    let mut mask = 0;
+    for part in days.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            None => (day(part)?, day(part)?),
+        };
+        if first > last {
+            return None;
+        }
+        for index in first..=last {
+            mask |= 1 << index;
+        }
+    }
+    Some(mask)
+}
+
+/// `HH:MM` as minutes since midnight, `24:00` is the end of the day.
+fn parse_time(time: &str) -> Option<u16> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let hours: u16 = hours.parse().ok()?;
+    let minutes: u16 = minutes.parse().ok()?;
+    (minutes < 60 && (hours < 24 || (hours == 24 && minutes == 0))).then_some(hours * 60 + minutes)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalTime {
+    /// Starting with Monday as 0.
+    pub weekday: u8,
+    /// Minutes since midnight.
+    pub minute: u16,
+}
+
+impl LocalTime {
+    pub fn now() -> Self {
+        // SAFETY: time accepts a null pointer, and tm is valid for writes for the whole call
+        let tm = unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            let mut tm: libc::tm = mem::zeroed();
+            libc::localtime_r(&now, &mut tm);
+            tm
+        };
+        LocalTime {
+            weekday: ((tm.tm_wday + 6) % 7) as u8,
+            minute: (tm.tm_hour * 60 + tm.tm_min) as u16,
+        }
+    }
+}
+
+/// Whether the time is within any of the windows, or there are no windows at all.
+pub fn is_active(windows: &[HoursWindow], time: LocalTime) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.contains(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(weekday: &str, time: &str) -> LocalTime {
+        LocalTime {
+            weekday: WEEKDAYS.iter().position(|day| *day == weekday).unwrap() as u8,
+            minute: parse_time(time).unwrap(),
+        }
+    }
+
+    #[test]
+    fn windows_contain_their_times() {
+        let cases = [
+            ("09:00-18:00", "wed", "09:00", true),
+            ("09:00-18:00", "wed", "17:59", true),
+            ("09:00-18:00", "wed", "18:00", false),
+            ("09:00-18:00", "wed", "08:59", false),
+            ("mon-fri 09:00-18:00", "fri", "12:00", true),
+            ("mon-fri 09:00-18:00", "sat", "12:00", false),
+            ("sat,sun 10:00-12:00", "sun", "11:00", true),
+            ("sat,sun 10:00-12:00", "mon", "11:00", false),
+            ("mon,wed-thu 10:00-12:00", "thu", "11:00", true),
+            ("mon,wed-thu 10:00-12:00", "tue", "11:00", false),
+            ("00:00-24:00", "sun", "23:59", true),
+            ("18:00-24:00", "tue", "00:00", false),
+            ("22:00-02:00", "tue", "23:00", true),
+            ("22:00-02:00", "tue", "01:59", true),
+            ("22:00-02:00", "tue", "02:00", false),
+            ("fri 22:00-02:00", "fri", "22:00", true),
+            ("fri 22:00-02:00", "sat", "01:00", true),
+            ("fri 22:00-02:00", "fri", "01:00", false),
+            ("fri 22:00-02:00", "sat", "22:00", false),
+            ("sun 23:00-01:00", "mon", "00:30", true),
+        ];
+        for (window, weekday, time, expected) in cases {
+            let parsed = HoursWindow::try_from(window.to_string()).unwrap();
+            assert_eq!(
+                parsed.contains(at(weekday, time)),
+                expected,
+                "{} at {} {}",
+                window,
+                weekday,
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_windows_are_rejected() {
+        let cases = [
+            "09:00",
+            "09:00-25:00",
+            "09:60-18:00",
+            "fri-mon 09:00-18:00",
+            "someday 09:00-18:00",
+            "24:00-02:00",
+            "09:00-09:00",
+        ];
+        for window in cases {
+            assert!(HoursWindow::try_from(window.to_string()).is_err(), "{}", window);
+        }
+    }
+}
@@ -0,0 +1,549 @@
+//! HTTP server for the status, e.g. for a dashboard or a Prometheus scraper on another machine.
+//!
+//! `GET /status` is answered with the same JSON object as the control socket's `status`,
+//! `GET /metrics` with the Prometheus text format. If a `token` is configured, requests need
+//! an `Authorization: Bearer <token>` header and are answered with `401 Unauthorized`
+//! otherwise. Built with the `https` feature, `tls_cert` and `tls_key` switch to HTTPS.
+//!
+//! Built with the `websocket` feature, `GET /events` upgrades to a WebSocket that gets the
+//! status as a text message right away and a message for every transition passed to
+//! [`HttpServer::publish`] after that, so dashboards don't need to poll. Browsers can't set
+//! headers on WebSockets, so the token may also be given as `/events?token=<token>` there.
+use crate::recording_watcher::{json_escape, StatusSource, TransitionEvent};
+use serde::Deserialize;
+use snafu::prelude::*;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(feature = "https")]
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Connections that don't send their request in time are dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests with longer headers are rejected.
+const MAX_HEADER_BYTES: u64 = 8192;
+
+/// How long WebSocket connections wait for transitions before checking on the client.
+#[cfg(feature = "websocket")]
+const EVENT_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// How long WebSocket connections wait for messages from the client, e.g. pings or a close,
+/// before waiting for transitions again.
+#[cfg(feature = "websocket")]
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Failed to listen for HTTP requests on [{}]", address))]
+    Bind {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    #[cfg(feature = "https")]
+    #[snafu(display("HTTPS needs both tls_cert and tls_key"))]
+    IncompleteTls,
+    #[cfg(feature = "https")]
+    #[snafu(display("Failed to read [{}]", path.display()))]
+    ReadPem {
+        path: PathBuf,
+        source: rustls_pki_types::pem::Error,
+    },
+    #[cfg(feature = "https")]
+    #[snafu(display("Invalid TLS certificate or key"))]
+    Tls { source: rustls::Error },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address and port to listen on, e.g. `127.0.0.1:9124`.
+    pub listen: SocketAddr,
+    /// Required as a bearer token on every request if set.
+    pub token: Option<String>,
+    /// PEM files with the certificate chain and the private key.
+    #[cfg(feature = "https")]
+    pub tls_cert: Option<PathBuf>,
+    #[cfg(feature = "https")]
+    pub tls_key: Option<PathBuf>,
+}
+
+struct Server {
+    token: Option<String>,
+    status: Arc<dyn StatusSource>,
+    /// One sender per WebSocket connection, see [`HttpServer::publish`].
+    clients: Mutex<Vec<Sender<String>>>,
+    #[cfg(feature = "https")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+/// A server running in the background.
+pub struct HttpServer {
+    /// The address actually listened on, which differs from the configured one for port `0`.
+    pub address: SocketAddr,
+    server: Arc<Server>,
+}
+
+impl HttpServer {
+    /// Sends the transition to all WebSocket clients, e.g. from a
+    /// [`crate::recording_watcher::RecordingWatcher::on_change`] observer. Doesn't block, each
+    /// connection has a queue of its own, so clients get every transition in order.
+    pub fn publish(&self, event: &TransitionEvent) {
+        let mut clients = self.server.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let message = event_json(event);
+        // clients that went away dropped their receiver
+        clients.retain(|client| client.send(message.clone()).is_ok());
+    }
+}
+
+/// The message for a transition: the state, the time as seconds since the epoch, the linked
+/// devices and the apps using them.
+fn event_json(event: &TransitionEvent) -> String {
+    let strings = |values: &[String]| -> String {
+        let quoted: Vec<String> = values
+            .iter()
+            .map(|value| format!("\"{}\"", json_escape(value)))
+            .collect();
+        quoted.join(", ")
+    };
+    let at = event
+        .transition
+        .at
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    format!(
+        "{{\"on_air\": {}, \"at\": {}, \"devices\": [{}], \"apps\": [{}]}}",
+        event.on_air,
+        at,
+        strings(&event.transition.devices),
+        strings(&event.transition.apps)
+    )
+}
+
+/// Accepts connections on a background thread and answers each on a thread of its own.
+pub fn spawn(config: &HttpConfig, status: Arc<dyn StatusSource>) -> Result<HttpServer, Error> {
+    let server = Arc::new(Server {
+        token: config.token.clone(),
+        status,
+        clients: Mutex::new(Vec::new()),
+        #[cfg(feature = "https")]
+        tls: tls_config(config)?,
+    });
+    let address = config.listen;
+    let listener = TcpListener::bind(address).context(BindSnafu { address })?;
+    let address = listener.local_addr().context(BindSnafu { address })?;
+    info!("Listening for HTTP requests on [{}]", address);
+    if server.token.is_none() && !address.ip().is_loopback() {
+        warn!("The HTTP status is reachable from other machines without a token");
+    }
+    let accepting = server.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = accepting.clone();
+                    thread::spawn(move || server.accept(stream));
+                }
+                Err(e) => warn!("failed to accept HTTP connection: {}", e),
+            }
+        }
+    });
+    Ok(HttpServer { address, server })
+}
+
+#[cfg(feature = "https")]
+fn tls_config(config: &HttpConfig) -> Result<Option<Arc<rustls::ServerConfig>>, Error> {
+    use rustls_pki_types::pem::PemObject;
+    use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+
+    let (cert, key) = match (&config.tls_cert, &config.tls_key) {
+        (None, None) => return Ok(None),
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return IncompleteTlsSnafu.fail(),
+    };
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .context(ReadPemSnafu { path: cert })?;
+    let key = PrivateKeyDer::from_pem_file(key).context(ReadPemSnafu { path: key })?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context(TlsSnafu)?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .context(TlsSnafu)?;
+    Ok(Some(Arc::new(tls)))
+}
+
+/// A plain or TLS connection.
+trait Connection: Read + Write {
+    #[cfg_attr(not(feature = "websocket"), allow(dead_code))]
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, Some(timeout))
+    }
+}
+
+#[cfg(feature = "https")]
+impl Connection for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.sock.set_read_timeout(Some(timeout))
+    }
+}
+
+impl Server {
+    fn accept(&self, stream: TcpStream) {
+        if let Err(e) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+            debug!("failed to set a timeout on HTTP connection: {}", e);
+            return;
+        }
+        #[cfg(feature = "https")]
+        if let Some(tls) = &self.tls {
+            match rustls::ServerConnection::new(tls.clone()) {
+                Ok(connection) => self.handle(rustls::StreamOwned::new(connection, stream)),
+                Err(e) => debug!("failed to start TLS session: {}", e),
+            }
+            return;
+        }
+        self.handle(stream);
+    }
+
+    fn handle(&self, mut stream: impl Connection) {
+        let request = match Request::read(&mut stream) {
+            Ok(request) => request,
+            Err(e) => {
+                debug!("failed to read HTTP request: {}", e);
+                return;
+            }
+        };
+        #[cfg(feature = "websocket")]
+        if request.path() == "/events" && self.authorized_for_events(&request) {
+            if let Some(key) = request.header("sec-websocket-key") {
+                self.stream_events(stream, key);
+                return;
+            }
+        }
+        let response = self.respond(&request);
+        debug!("HTTP [{} {}]: {}", request.method, request.path, response.status);
+        if let Err(e) = stream
+            .write_all(&response.to_bytes())
+            .and_then(|_| stream.flush())
+        {
+            debug!("failed to answer HTTP request: {}", e);
+        }
+    }
+
+    fn respond(&self, request: &Request) -> Response {
+        if !self.authorized(request) {
+            let mut response = Response::text("401 Unauthorized", "unauthorized");
+            response.headers.push(("WWW-Authenticate", "Bearer"));
+            return response;
+        }
+        match (request.method.as_str(), request.path()) {
+            ("GET", "/status") => Response {
+                status: "200 OK",
+                content_type: "application/json",
+                headers: Vec::new(),
+                body: self.status.status().to_json() + "\n",
+            },
+            ("GET", "/metrics") => Response {
+                status: "200 OK",
+                content_type: "text/plain; version=0.0.4",
+                headers: Vec::new(),
+                body: self.status.status().to_prometheus() + "\n",
+            },
+            ("GET", _) => Response::text("404 Not Found", "not found"),
+            _ => Response::text("405 Method Not Allowed", "method not allowed"),
+        }
+    }
+
+    /// Sends the status, then every published transition until the client goes away.
+    #[cfg(feature = "websocket")]
+    fn stream_events(&self, mut stream: impl Connection, key: &str) {
+        use std::sync::mpsc::{self, RecvTimeoutError};
+        use tungstenite::protocol::Role;
+        use tungstenite::{Message, WebSocket};
+
+        // before the handshake, so nothing published after it is missed
+        let (sender, events) = mpsc::channel();
+        self.clients.lock().unwrap().push(sender);
+        let handshake = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            tungstenite::handshake::derive_accept_key(key.as_bytes())
+        );
+        let upgraded = stream
+            .write_all(handshake.as_bytes())
+            .and_then(|_| stream.flush())
+            .and_then(|_| stream.set_read_timeout(CLIENT_TIMEOUT));
+        if let Err(e) = upgraded {
+            debug!("failed to upgrade to a WebSocket: {}", e);
+            return;
+        }
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        let mut message = Some(self.status.status().to_json());
+        loop {
+            if let Some(message) = message.take() {
+                if let Err(e) = socket.send(Message::text(message)) {
+                    debug!("failed to send to WebSocket client: {}", e);
+                    return;
+                }
+            }
+            match events.recv_timeout(EVENT_TIMEOUT) {
+                Ok(event) => {
+                    message = Some(event);
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            // also answers pings, and close frames until the connection is closed
+            match socket.read() {
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(tungstenite::Error::ConnectionClosed) => {
+                    debug!("WebSocket client disconnected");
+                    return;
+                }
+                Err(e) => {
+                    debug!("WebSocket client went away: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        request
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+    }
+
+    #[cfg(feature = "websocket")]
+    fn authorized_for_events(&self, request: &Request) -> bool {
+        let in_query = match (&self.token, request.query("token")) {
+            (Some(token), Some(given)) => constant_time_eq(given.as_bytes(), token.as_bytes()),
+            _ => false,
+        };
+        in_query || self.authorized(request)
+    }
+}
+
+/// Compares without returning early, so the time taken doesn't tell how much of a guessed
+/// token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+struct Request {
+    method: String,
+    path: String,
+    /// Names in lower case.
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Reads the request line and headers, a body is ignored.
+    fn read(stream: &mut impl Read) -> io::Result<Self> {
+        let mut reader = BufReader::new(stream.take(MAX_HEADER_BYTES));
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid request line [{}]", line.trim()),
+            ));
+        };
+        let (method, path) = (method.to_string(), path.to_string());
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "headers ended early or are too long",
+                ));
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+        Ok(Request {
+            method,
+            path,
+            headers,
+        })
+    }
+
+    /// The path without the query.
+    fn path(&self) -> &str {
+        self.path.split('?').next().unwrap_or_default()
+    }
+
+    /// Values are taken as they are, without percent decoding.
+    #[cfg(feature = "websocket")]
+    fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.path.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    headers: Vec<(&'static str, &'static str)>,
+    body: String,
+}
+
+impl Response {
+    fn text(status: &'static str, body: &str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain",
+            headers: Vec::new(),
+            body: format!("{}\n", body),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(self.body.as_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording_watcher::Status;
+
+    struct OnAir;
+
+    impl StatusSource for OnAir {
+        fn status(&self) -> Status {
+            Status {
+                on_air: true,
+                ..Status::default()
+            }
+        }
+    }
+
+    fn get(address: SocketAddr, path: &str, token: Option<&str>) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n", path);
+        if let Some(token) = token {
+            request.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn requests_without_the_token_are_unauthorized() {
+        let config: HttpConfig =
+            toml::from_str("listen = \"127.0.0.1:0\"\ntoken = \"secret\"").unwrap();
+        let address = spawn(&config, Arc::new(OnAir)).unwrap().address;
+
+        let response = get(address, "/status", None);
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", response);
+        assert!(response.contains("\r\nWWW-Authenticate: Bearer\r\n"), "{}", response);
+        let response = get(address, "/status", Some("guess"));
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", response);
+
+        let response = get(address, "/status", Some("secret"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\r\n\r\n{\"on_air\": true, "), "{}", response);
+        let response = get(address, "/metrics?format=text", Some("secret"));
+        assert!(response.contains("\nonair_buddy_on_air 1\n"), "{}", response);
+        let response = get(address, "/other", Some("secret"));
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn websocket_clients_get_every_transition() {
+        use crate::recording_watcher::Transition;
+
+        let config: HttpConfig =
+            toml::from_str("listen = \"127.0.0.1:0\"\ntoken = \"secret\"").unwrap();
+        let server = spawn(&config, Arc::new(OnAir)).unwrap();
+        let address = server.address;
+        let stream = TcpStream::connect(address).unwrap();
+        let url = format!("ws://{}/events", address);
+        assert!(tungstenite::client::client(url, stream).is_err());
+        let stream = TcpStream::connect(address).unwrap();
+        let url = format!("ws://{}/events?token=secret", address);
+        let (mut socket, _) = tungstenite::client::client(url, stream).unwrap();
+        let message = socket.read().unwrap().into_text().unwrap();
+        assert!(message.starts_with("{\"on_air\": true, \"paused\""), "{}", message);
+
+        for on_air in [false, true] {
+            let transition = Transition {
+                at: UNIX_EPOCH + Duration::from_secs(1700000000),
+                devices: if on_air { vec!["Mic".to_string()] } else { Vec::new() },
+                labels: Vec::new(),
+                apps: if on_air { vec!["Zoom".to_string()] } else { Vec::new() },
+                scopes: Vec::new(),
+            };
+            server.publish(&TransitionEvent { on_air, transition });
+        }
+        let message = socket.read().unwrap().into_text().unwrap();
+        assert_eq!(
+            message.as_str(),
+            "{\"on_air\": false, \"at\": 1700000000, \"devices\": [], \"apps\": []}"
+        );
+        let message = socket.read().unwrap().into_text().unwrap();
+        assert_eq!(
+            message.as_str(),
+            "{\"on_air\": true, \"at\": 1700000000, \"devices\": [\"Mic\"], \
+             \"apps\": [\"Zoom\"]}"
+        );
+        socket.close(None).unwrap();
+        while socket.read().is_ok() {}
+    }
+}
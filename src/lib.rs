@@ -0,0 +1,32 @@
+//! Watches PipeWire for apps using in scope microphones and runs actors on every transition
+//! between on and off air. The `onair-buddy` binary is a thin wrapper around this crate, which
+//! can also be embedded:
+//!
+//! ```no_run
+//! use callindicator::actors::CompositeActor;
+//! use callindicator::config::Config;
+//! use callindicator::recording_watcher::RecordingWatcher;
+//!
+//! let mut watcher = RecordingWatcher::new(&Config::default(), CompositeActor::new(Vec::new()));
+//! watcher.on_change(|event| println!("on air: {}", event.on_air));
+//! let (_commands, receiver) = pipewire::channel::channel();
+//! watcher.start_watcher(receiver).unwrap();
+//! ```
+/// The name shown to users, the crate itself is called `callindicator`.
+pub const APP_NAME: &str = "onair-buddy";
+
+pub mod actors;
+pub mod check;
+pub mod cli;
+pub mod config;
+pub mod control;
+pub mod diagnose;
+pub mod event_log;
+pub mod flag_file;
+pub mod hours;
+pub mod http;
+pub mod pattern;
+pub mod recording_watcher;
+pub mod simulate;
+#[cfg(feature = "tray")]
+pub mod tray;
@@ -1,19 +1,165 @@
-mod recording_watcher;
+use callindicator::cli::{Cli, LogTarget};
+use callindicator::config::Sources;
+use callindicator::control::{self, ControlSocket};
+use callindicator::recording_watcher::{OnAirActor, RecordingWatcher};
+#[cfg(feature = "tray")]
+use callindicator::tray;
+use callindicator::{actors, check, diagnose, event_log, flag_file, http, simulate};
+use clap::Parser;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
+use tracing::{info, warn, Level};
+#[cfg(feature = "journald")]
+use tracing_subscriber::{filter::LevelFilter, prelude::*};
 
-use std::collections::HashSet;
-use tracing::info;
-use crate::recording_watcher::{DebugActor, RecordingWatcher};
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // the Debug output `main` would print hides the explanations in the messages
+            let mut message = e.to_string();
+            let mut source = e.source();
+            while let Some(cause) = source {
+                let cause_message = cause.to_string();
+                if !message.contains(&cause_message) {
+                    message.push_str(&format!(": {}", cause_message));
+                }
+                source = cause.source();
+            }
+            eprintln!("Error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt().init();
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    init_logging(&cli);
     info!("Startup..");
+    info!("Compiled in features: {}", compiled_features());
+
+    let sources = Sources::load(&cli)?;
+    let config = sources.config()?;
+    if cli.diagnose {
+        return diagnose::run(&config);
+    }
+    if cli.check_config {
+        return check::run(&config);
+    }
+    if cli.status {
+        return Ok(control::print_status(config.control_socket.as_deref())?);
+    }
+    let (commands, command_receiver) = pipewire::channel::channel();
+    let configured_actors = Arc::new(actors::SwappableActor::new(actors::from_config(&config)?));
+    let actor: Box<dyn OnAirActor> = Box::new(configured_actors.clone());
+    let mut watcher = RecordingWatcher::new(&config, actor);
 
-    let mut devices_in_scope = HashSet::new();
-    devices_in_scope.insert("Built-in Audio Analog Stereo".to_string());
-    devices_in_scope.insert("M300-XT v1.06".to_string());
+    #[cfg(feature = "tray")]
+    if cli.tray {
+        tray::spawn(commands.clone(), watcher.status_source())?;
+    }
+    if let Some(path) = &config.flag_file {
+        flag_file::watch(path, commands.clone())?;
+    }
+    let _control_socket = match &config.control_socket {
+        Some(path) => Some(ControlSocket::spawn(
+            path,
+            commands,
+            configured_actors,
+            sources,
+            watcher.status_source(),
+            config.control_same_user_only,
+        )?),
+        None => None,
+    };
+    if let Some(http) = &config.http {
+        let server = http::spawn(http, watcher.status_source())?;
+        watcher.on_change(move |event| server.publish(&event));
+    }
+    if let Some(script) = &cli.simulate {
+        watcher.run_simulation(simulate::load(script)?);
+        return Ok(());
+    }
+    if let Some(log) = &cli.replay {
+        watcher.run_replay(event_log::load(log)?);
+        return Ok(());
+    }
+    if let Some(path) = &cli.record_events {
+        info!("Recording registry events to [{}]", path.display());
+        watcher.record_events(event_log::EventLog::create(path)?);
+    }
+    if !config.startup_delay.is_zero() {
+        info!("Waiting [{:?}] before connecting", config.startup_delay);
+        thread::sleep(config.startup_delay);
+    }
 
-    let mut devices_ignored = HashSet::new();
-    devices_ignored.insert("PulseAudio Volume Control".to_string());
+    #[cfg(feature = "tokio")]
+    return tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()?
+        .block_on(watcher.start_watcher_async(command_receiver));
+    #[cfg(not(feature = "tokio"))]
+    watcher.start_watcher(command_receiver)
+}
+
+fn init_logging(cli: &Cli) {
+    let level = if cli.quiet { Level::ERROR } else { Level::INFO };
+    let fallback = match cli.log_target {
+        LogTarget::Stderr => None,
+        #[cfg(feature = "journald")]
+        LogTarget::Journald => match tracing_journald::layer() {
+            Ok(journald) => {
+                // without a prefix, fields like `onair` can be matched as `ONAIR=1`
+                tracing_subscriber::registry()
+                    .with(journald.with_field_prefix(None))
+                    .with(LevelFilter::from_level(level))
+                    .init();
+                return;
+            }
+            Err(e) => Some(format!(
+                "journald is not available, logging to stderr: {}",
+                e
+            )),
+        },
+        #[cfg(not(feature = "journald"))]
+        LogTarget::Journald => {
+            Some("compiled without the journald feature, logging to stderr".to_string())
+        }
+    };
+    // stdout is reserved for --emit-events
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(level)
+        .init();
+    if let Some(fallback) = fallback {
+        warn!("{}", fallback);
+    }
+}
 
-    RecordingWatcher::new(devices_in_scope, devices_ignored, DebugActor{}).start_watcher()
+fn compiled_features() -> String {
+    let features = [
+        ("tray", cfg!(feature = "tray")),
+        ("tokio", cfg!(feature = "tokio")),
+        ("obs", cfg!(feature = "obs")),
+        ("idle-inhibit", cfg!(feature = "idle-inhibit")),
+        ("notify", cfg!(feature = "notify")),
+        ("serial", cfg!(feature = "serial")),
+        ("ble", cfg!(feature = "ble")),
+        ("journald", cfg!(feature = "journald")),
+        ("https", cfg!(feature = "https")),
+        ("websocket", cfg!(feature = "websocket")),
+        ("x11", cfg!(feature = "x11")),
+    ];
+    let enabled: Vec<&str> = features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    if enabled.is_empty() {
+        "none".to_string()
+    } else {
+        enabled.join(", ")
+    }
 }
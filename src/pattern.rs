@@ -0,0 +1,23 @@
+/// Matches a name against a pattern in which `*` stands for any number of characters.
+/// Patterns without a `*` have to match exactly.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(mut remaining) = name.strip_prefix(prefix) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let suffix = parts.pop().unwrap_or_default();
+    for part in parts {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.len() >= suffix.len() && remaining.ends_with(suffix)
+}
+
+pub fn matches_any<'a>(patterns: impl IntoIterator<Item = &'a String>, name: &str) -> bool {
+    patterns.into_iter().any(|pattern| matches(pattern, name))
+}
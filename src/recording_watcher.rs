@@ -1,12 +1,35 @@
-use pipewire::prelude::ReadableDict;
-use pipewire::spa::{ForeignDict, ParsableValue};
+use pipewire::prelude::{ReadableDict, WritableDict};
+use pipewire::spa::ParsableValue;
 use pipewire::types::ObjectType;
-use pipewire::{Context, MainLoop, keys};
+use crate::config::{
+    Config, FanOutConfig, LinkCountScope, LinkRule, LinkRuleAction, MalformedLinkPolicy,
+    ScopeConfig, SmoothingConfig, UnknownAppPolicy, WarningConfig,
+};
+use crate::event_log::{EventLog, LoggedEvent, TimedLoggedEvent};
+use crate::hours::{self, HoursWindow, LocalTime};
+use crate::pattern;
+use crate::simulate::{SimulatedEvent, TimedEvent};
+use pipewire::channel::{AttachedReceiver, Receiver};
+use pipewire::link::{Link, LinkListener, LinkState};
+use pipewire::metadata::{Metadata, MetadataListener};
+use pipewire::node::{Node, NodeListener};
+use pipewire::registry::{self, GlobalObject, Registry};
+use pipewire::spa::ForeignDict;
+use pipewire::{
+    Context, Core, MainLoop, Properties, Signal, SignalSource, TimerSource, PW_ID_CORE, keys,
+    properties,
+};
+use serde::Deserialize;
 use snafu::prelude::*;
-use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{self, Debug};
 use std::process::Command;
+use std::rc::Rc;
 use std::string::ToString;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
 #[derive(Debug, Snafu)]
@@ -15,70 +38,1041 @@ pub enum Error {
     NoOutputNode { props: String },
     #[snafu(display("No input node id present in properties"))]
     NoInputNode { props: String },
+    #[snafu(display("Value [{}] of [{}] is not a node id", value, key))]
+    InvalidNodeId { key: &'static str, value: String },
+    #[snafu(display(
+        "Failed to connect to PipeWire remote [{}], make sure PipeWire is installed and \
+         running for this user (e.g. `systemctl --user status pipewire`) or set remote to the \
+         instance to use",
+        remote
+    ))]
+    PipeWireUnavailable {
+        remote: String,
+        source: pipewire::Error,
+    },
 }
 
-pub trait OnAirActor {
-    fn go_on_air(&self);
-    fn go_off_air(&self);
+/// Object properties as handed out by the registry, or built by hand for simulations.
+pub trait Props: ReadableDict + Debug {}
+
+impl<D: ReadableDict + Debug> Props for D {}
+
+pub type HookError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Context handed to actors on every transition.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub at: SystemTime,
+    /// Names of the in scope devices that currently have links, sorted and deduplicated.
+    pub devices: Vec<String>,
+    /// Configured label of each device, or its name if it has none.
+    pub labels: Vec<String>,
+    /// Names of the nodes the devices are linked to, sorted and deduplicated.
+    pub apps: Vec<String>,
+    /// Named app specific scopes the links count for, in config order. When going off air
+    /// these are the scopes of the on air period that ends.
+    pub scopes: Vec<String>,
+}
+
+impl Transition {
+    /// All labels joined for display, empty when no device is linked.
+    pub fn label(&self) -> String {
+        self.labels.join(", ")
+    }
+}
+
+/// What observers registered with [`RecordingWatcher::on_change`] are called with.
+#[derive(Debug, Clone)]
+pub struct TransitionEvent {
+    pub on_air: bool,
+    pub transition: Transition,
+}
+
+type Observer = Box<dyn Fn(TransitionEvent) + Send + Sync>;
+
+/// Observers adapted into an actor, so they run whenever the hooks do.
+#[derive(Default)]
+struct Observers(Vec<Observer>);
+
+impl Observers {
+    fn notify(&self, on_air: bool, transition: &Transition) {
+        for observer in &self.0 {
+            observer(TransitionEvent {
+                on_air,
+                transition: transition.clone(),
+            });
+        }
+    }
+}
+
+impl OnAirActor for Observers {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.notify(true, transition);
+        Ok(())
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        self.notify(false, transition);
+        Ok(())
+    }
+}
+
+/// An in scope device linked to fewer or more apps than `fan_out` expects.
+#[derive(Debug, Clone)]
+pub struct FanOutAlarm {
+    pub device: String,
+    /// Configured label of the device, or its name if it has none.
+    pub label: String,
+    /// Names of the nodes the device is linked to, one per active link.
+    pub apps: Vec<String>,
+    pub expected: FanOutConfig,
+}
+
+pub trait OnAirActor: Send + Sync {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError>;
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError>;
+
+    /// Called whenever the number of distinct apps using in scope devices changes, for
+    /// indicators showing more than on and off.
+    fn on_level_change(&self, _count: usize) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Called when having been on air for longer than the configured warning threshold.
+    fn go_warning(&self, _transition: &Transition, _on_air_for: Duration) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Called when the linked devices change while staying on air, for actors following each
+    /// device separately.
+    fn on_devices_change(&self, _transition: &Transition) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Called when a device's number of active links leaves the range configured in `fan_out`.
+    fn go_fan_out_alarm(&self, _alarm: &FanOutAlarm) -> Result<(), HookError> {
+        Ok(())
+    }
+}
+
+impl<A: OnAirActor + ?Sized> OnAirActor for Box<A> {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        (**self).go_on_air(transition)
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        (**self).go_off_air(transition)
+    }
+
+    fn on_level_change(&self, count: usize) -> Result<(), HookError> {
+        (**self).on_level_change(count)
+    }
+
+    fn go_warning(&self, transition: &Transition, on_air_for: Duration) -> Result<(), HookError> {
+        (**self).go_warning(transition, on_air_for)
+    }
+
+    fn on_devices_change(&self, transition: &Transition) -> Result<(), HookError> {
+        (**self).on_devices_change(transition)
+    }
+
+    fn go_fan_out_alarm(&self, alarm: &FanOutAlarm) -> Result<(), HookError> {
+        (**self).go_fan_out_alarm(alarm)
+    }
+}
+
+impl<A: OnAirActor + ?Sized> OnAirActor for Arc<A> {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
+        (**self).go_on_air(transition)
+    }
+
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
+        (**self).go_off_air(transition)
+    }
+
+    fn on_level_change(&self, count: usize) -> Result<(), HookError> {
+        (**self).on_level_change(count)
+    }
+
+    fn go_warning(&self, transition: &Transition, on_air_for: Duration) -> Result<(), HookError> {
+        (**self).go_warning(transition, on_air_for)
+    }
+
+    fn on_devices_change(&self, transition: &Transition) -> Result<(), HookError> {
+        (**self).on_devices_change(transition)
+    }
+
+    fn go_fan_out_alarm(&self, alarm: &FanOutAlarm) -> Result<(), HookError> {
+        (**self).go_fan_out_alarm(alarm)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    pub on_air_urgency: Option<Urgency>,
+    pub off_air_urgency: Option<Urgency>,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    pub icon: Option<String>,
+    /// Texts as in [`NotificationTemplate`], the built-in ones are used if unset.
+    pub on_air_summary: Option<String>,
+    pub on_air_body: Option<String>,
+    pub off_air_summary: Option<String>,
+    pub off_air_body: Option<String>,
+    /// Update the previous notification in place instead of stacking them, needs
+    /// notify-send 0.8 or newer. On by default.
+    pub replace: bool,
+    /// Commands tried in order until one succeeds, with the message as last argument.
+    /// [`DEFAULT_NOTIFIERS`] if empty.
+    pub commands: Vec<String>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        NotificationConfig {
+            on_air_urgency: None,
+            off_air_urgency: None,
+            timeout: None,
+            icon: None,
+            on_air_summary: None,
+            on_air_body: None,
+            off_air_summary: None,
+            off_air_body: None,
+            replace: true,
+            commands: Vec::new(),
+        }
+    }
+}
+
+pub const DEFAULT_NOTIFIERS: [&str; 3] = ["notify-send", "dunstify", "kdialog --passivepopup"];
+
+/// Notification texts and icon of a scope, `%label%` is replaced with the labels. Unset
+/// values fall back to `[notification]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationTemplate {
+    pub on_air_summary: Option<String>,
+    pub on_air_body: Option<String>,
+    pub off_air_summary: Option<String>,
+    pub off_air_body: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// What a single notification shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationContent {
+    pub summary: String,
+    pub body: Option<String>,
+    pub icon: Option<String>,
+}
+
+/// The templates of `[notification]` and the named scopes. Each value comes from the first
+/// scope of the transition that sets it, then from `[notification]`.
+#[derive(Debug, Clone, Default)]
+pub struct NotificationTemplates {
+    global: NotificationTemplate,
+    scopes: Vec<(String, NotificationTemplate)>,
+}
+
+impl NotificationTemplates {
+    pub fn new(config: &NotificationConfig, scopes: &[ScopeConfig]) -> Self {
+        let mut templates = Vec::new();
+        for scope in scopes {
+            match &scope.name {
+                Some(name) => templates.push((name.clone(), scope.notification.clone())),
+                None if scope.notification != NotificationTemplate::default() => {
+                    warn!("Notification templates only apply to scopes with a name")
+                }
+                None => {}
+            }
+        }
+        NotificationTemplates {
+            global: NotificationTemplate {
+                on_air_summary: config.on_air_summary.clone(),
+                on_air_body: config.on_air_body.clone(),
+                off_air_summary: config.off_air_summary.clone(),
+                off_air_body: config.off_air_body.clone(),
+                icon: config.icon.clone(),
+            },
+            scopes: templates,
+        }
+    }
+
+    fn pick(
+        &self,
+        transition: &Transition,
+        field: impl Fn(&NotificationTemplate) -> &Option<String>,
+    ) -> Option<String> {
+        transition
+            .scopes
+            .iter()
+            .filter_map(|name| self.scopes.iter().find(|(scope, _)| scope == name))
+            .map(|(_, template)| template)
+            .chain([&self.global])
+            .find_map(|template| field(template).as_ref())
+            .map(|text| text.replace("%label%", &transition.label()))
+    }
+
+    pub fn on_air(&self, transition: &Transition) -> NotificationContent {
+        let summary = self
+            .pick(transition, |template| &template.on_air_summary)
+            .unwrap_or_else(|| {
+                let mut summary = "Going on air!".to_string();
+                if !transition.labels.is_empty() {
+                    summary.push_str(&format!(" ({})", transition.label()));
+                }
+                summary
+            });
+        NotificationContent {
+            summary,
+            body: self.pick(transition, |template| &template.on_air_body),
+            icon: self.pick(transition, |template| &template.icon),
+        }
+    }
+
+    pub fn off_air(&self, transition: &Transition) -> NotificationContent {
+        NotificationContent {
+            summary: self
+                .pick(transition, |template| &template.off_air_summary)
+                .unwrap_or_else(|| "Going off air!".to_string()),
+            body: self.pick(transition, |template| &template.off_air_body),
+            icon: self.pick(transition, |template| &template.icon),
+        }
+    }
+
+    pub fn warning(&self, transition: &Transition, on_air_for: Duration) -> NotificationContent {
+        NotificationContent {
+            summary: format!("Still on air after {}", format_whole_seconds(on_air_for)),
+            body: None,
+            icon: self.pick(transition, |template| &template.icon),
+        }
+    }
+
+    pub fn fan_out(&self, alarm: &FanOutAlarm) -> NotificationContent {
+        let mut body = format!("Expected {} links", alarm.expected);
+        if !alarm.apps.is_empty() {
+            body.push_str(&format!(", linked to {}", alarm.apps.join(", ")));
+        }
+        NotificationContent {
+            summary: format!("{} has {} links", alarm.label, alarm.apps.len()),
+            body: Some(body),
+            icon: self.global.icon.clone(),
+        }
+    }
+}
+
+/// Long options of notifiers that take the notification settings, they differ in naming.
+struct NotifierOptions {
+    urgency: &'static str,
+    expire_time: &'static str,
+    icon: &'static str,
+    print_id: &'static str,
+    replace_id: &'static str,
+}
+
+impl NotifierOptions {
+    fn of(program: &str) -> Option<Self> {
+        match program.rsplit('/').next() {
+            Some("notify-send") => Some(NotifierOptions {
+                urgency: "--urgency",
+                expire_time: "--expire-time",
+                icon: "--icon",
+                print_id: "--print-id",
+                replace_id: "--replace-id",
+            }),
+            Some("dunstify") => Some(NotifierOptions {
+                urgency: "--urgency",
+                expire_time: "--timeout",
+                icon: "--icon",
+                print_id: "--printid",
+                replace_id: "--replace",
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    fn as_arg(&self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+/// Shows desktop notifications with the first of the notifier commands that works. The
+/// notification settings are only passed to `notify-send` and `dunstify`, others just get
+/// the message.
+pub struct DebugActor {
+    config: NotificationConfig,
+    templates: NotificationTemplates,
+    /// Notifier commands split into program and arguments.
+    notifiers: Vec<Vec<String>>,
+    /// Index of the notifier that worked last, to log when that changes.
+    working: Mutex<Option<usize>>,
+    /// Id of the last notification, to replace it with the next one.
+    last_id: Mutex<Option<String>>,
 }
 
-pub struct DebugActor {}
+impl DebugActor {
+    pub fn new(config: NotificationConfig, scopes: &[ScopeConfig]) -> Self {
+        let notifiers = if config.commands.is_empty() {
+            DEFAULT_NOTIFIERS.iter().map(|command| command.to_string()).collect()
+        } else {
+            config.commands.clone()
+        };
+        DebugActor {
+            notifiers: notifiers
+                .iter()
+                .map(|command| command.split_whitespace().map(str::to_string).collect())
+                .filter(|notifier: &Vec<String>| !notifier.is_empty())
+                .collect(),
+            templates: NotificationTemplates::new(&config, scopes),
+            config,
+            working: Mutex::new(None),
+            last_id: Mutex::new(None),
+        }
+    }
+
+    fn notify(
+        &self,
+        content: &NotificationContent,
+        urgency: Option<Urgency>,
+    ) -> Result<(), HookError> {
+        let mut errors = Vec::new();
+        for (index, notifier) in self.notifiers.iter().enumerate() {
+            match self.run_notifier(notifier, content, urgency) {
+                Ok(()) => {
+                    let mut working = self.working.lock().unwrap();
+                    if *working != Some(index) {
+                        info!("Showing notifications with [{}]", notifier.join(" "));
+                        *working = Some(index);
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    debug!("notifier [{}] failed: {}", notifier.join(" "), e);
+                    errors.push(format!("{}: {}", notifier[0], e));
+                }
+            }
+        }
+        Err(format!("no notifier worked [{}]", errors.join(", ")).into())
+    }
+
+    fn run_notifier(
+        &self,
+        notifier: &[String],
+        content: &NotificationContent,
+        urgency: Option<Urgency>,
+    ) -> Result<(), HookError> {
+        let mut command = Command::new(&notifier[0]);
+        command.args(&notifier[1..]);
+        let options = NotifierOptions::of(&notifier[0]);
+        let mut last_id = self.last_id.lock().unwrap();
+        if let Some(options) = &options {
+            if let Some(urgency) = urgency {
+                command.arg(format!("{}={}", options.urgency, urgency.as_arg()));
+            }
+            if let Some(timeout) = self.config.timeout {
+                command.arg(format!("{}={}", options.expire_time, timeout.as_millis()));
+            }
+            if let Some(icon) = &content.icon {
+                command.arg(format!("{}={}", options.icon, icon));
+            }
+            if self.config.replace {
+                command.arg(options.print_id);
+                if let Some(id) = last_id.as_ref() {
+                    command.arg(format!("{}={}", options.replace_id, id));
+                }
+            }
+        }
+        match (&options, &content.body) {
+            (Some(_), Some(body)) => command.arg(&content.summary).arg(body),
+            (None, Some(body)) => command.arg(format!("{}\n{}", content.summary, body)),
+            (_, None) => command.arg(&content.summary),
+        };
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(format!("exited with [{}]", output.status).into());
+        }
+        if options.is_some() && self.config.replace {
+            let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            *last_id = Some(id).filter(|id| !id.is_empty());
+        }
+        Ok(())
+    }
+}
 
 impl OnAirActor for DebugActor {
-    fn go_on_air(&self) {
+    fn go_on_air(&self, transition: &Transition) -> Result<(), HookError> {
         warn!("going on air!");
-        Command::new("sh")
-            .arg("-c")
-            .arg("notify-send \"Going on air!\"")
-            .output()
-            .expect("failed to execute process");
+        self.notify(&self.templates.on_air(transition), self.config.on_air_urgency)
     }
 
-    fn go_off_air(&self) {
+    fn go_off_air(&self, transition: &Transition) -> Result<(), HookError> {
         warn!("going off air!");
-        Command::new("sh")
-            .arg("-c")
-            .arg("notify-send \"Going off air!\"")
-            .output()
-            .expect("failed to execute process");
+        self.notify(&self.templates.off_air(transition), self.config.off_air_urgency)
+    }
+
+    fn go_warning(&self, transition: &Transition, on_air_for: Duration) -> Result<(), HookError> {
+        let content = self.templates.warning(transition, on_air_for);
+        warn!("{}", content.summary);
+        self.notify(&content, self.config.on_air_urgency)
+    }
+
+    fn go_fan_out_alarm(&self, alarm: &FanOutAlarm) -> Result<(), HookError> {
+        self.notify(&self.templates.fan_out(alarm), self.config.on_air_urgency)
+    }
+}
+/// Scope entries with this prefix match the properties of the device a node belongs to.
+pub const DEVICE_SCOPE_PREFIX: &str = "device:";
+/// Scope and ignore entries with this prefix hold a node id, which is only stable for the
+/// lifetime of a PipeWire session.
+pub const ID_SCOPE_PREFIX: &str = "id:";
+
+/// How often time based state (delays, timeouts) is re-evaluated.
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Input node of manual links, their own ids count down from here to stay clear of PipeWire's.
+const MANUAL_TARGET: u32 = u32::MAX - 1;
+
+/// Snapshot of a running watcher, e.g. for the control socket or the HTTP server.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    pub on_air: bool,
+    pub paused: bool,
+    /// Number of distinct apps using in scope devices.
+    pub apps: usize,
+    /// Names of the in scope devices that currently have links.
+    pub devices: Vec<String>,
+    /// Active in scope links, ordered by id.
+    pub links: Vec<LinkStatus>,
+    /// Entries of `devices_in_scope` that didn't match any node or device so far, sorted.
+    pub unmatched: Vec<String>,
+    /// Most recent transitions, oldest first.
+    pub history: Vec<TransitionReason>,
+    /// Kept even if `history_size` is zero.
+    pub last_transition: Option<TransitionReason>,
+    /// Transitions to on and off air since startup.
+    pub transitions: u64,
+    pub hook_timings: HookTimings,
+    pub counters: Counters,
+    pub link_latency: LinkLatency,
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkStatus {
+    pub id: u32,
+    pub device: String,
+    pub target: String,
+    /// Format of the device's port as PipeWire describes it, e.g. `32 bit float mono audio`.
+    pub format: Option<String>,
+    pub direction: LinkDirection,
+}
+
+/// Which way a link carries media, going by the media classes of both ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkDirection {
+    /// A device feeding an app, like a microphone in a call.
+    Capture,
+    /// An app feeding a device, like a speaker.
+    Playback,
+    /// Neither end announced a telling media class.
+    Unknown,
+}
+
+impl LinkDirection {
+    fn classify(output_class: Option<&str>, input_class: Option<&str>) -> Self {
+        let output_class = output_class.unwrap_or_default();
+        let input_class = input_class.unwrap_or_default();
+        if output_class.contains("Source") || input_class.starts_with("Stream/Input") {
+            LinkDirection::Capture
+        } else if output_class.starts_with("Stream/Output") || input_class.contains("Sink") {
+            LinkDirection::Playback
+        } else {
+            LinkDirection::Unknown
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkDirection::Capture => "capture",
+            LinkDirection::Playback => "playback",
+            LinkDirection::Unknown => "unknown",
+        }
+    }
+}
+
+impl Status {
+    pub fn to_json(&self) -> String {
+        let devices: Vec<String> = self.devices.iter().map(|d| json_string(d)).collect();
+        let unmatched: Vec<String> = self.unmatched.iter().map(|e| json_string(e)).collect();
+        let history: Vec<String> = self.history.iter().map(TransitionReason::to_json).collect();
+        let links: Vec<String> = self
+            .links
+            .iter()
+            .map(|link| {
+                format!(
+                    "{{\"id\": {}, \"device\": {}, \"target\": {}, \"format\": {}, \
+                     \"direction\": \"{}\"}}",
+                    link.id,
+                    json_string(&link.device),
+                    json_string(&link.target),
+                    link.format.as_deref().map_or("null".to_string(), json_string),
+                    link.direction.as_str()
+                )
+            })
+            .collect();
+        let last_transition = self
+            .last_transition
+            .as_ref()
+            .map_or("null".to_string(), TransitionReason::to_json);
+        let latency = self
+            .link_latency
+            .average()
+            .map_or("null".to_string(), |average| average.as_millis().to_string());
+        format!(
+            "{{\"on_air\": {}, \"paused\": {}, \"apps\": {}, \"devices\": [{}], \"links\": [{}], \
+             \"unmatched\": [{}], \"history\": [{}], \"last_transition\": {}, \"transitions\": {}, \
+             \"hooks\": {{\"runs\": {}, \"average_ms\": {}, \"max_ms\": {}}}, \"counters\": \
+             {{\"malformed_links\": {}, \"parse_errors\": {}, \"hook_failures\": {}}}, \
+             \"link_latency\": {{\"devices\": {}, \"average_ms\": {}}}}}",
+            self.on_air,
+            self.paused,
+            self.apps,
+            devices.join(", "),
+            links.join(", "),
+            unmatched.join(", "),
+            history.join(", "),
+            last_transition,
+            self.transitions,
+            self.hook_timings.runs,
+            self.hook_timings.average().as_millis(),
+            self.hook_timings.max.as_millis(),
+            self.counters.malformed_links,
+            self.counters.parse_errors,
+            self.counters.hook_failures,
+            self.link_latency.count,
+            latency
+        )
+    }
+
+    /// The status in the Prometheus text exposition format, with a gauge per active link.
+    pub fn to_prometheus(&self) -> String {
+        fn metric(metrics: &mut String, kind: &str, name: &str, help: &str, value: impl fmt::Display) {
+            metrics.push_str(&format!("# HELP onair_buddy_{} {}\n", name, help));
+            metrics.push_str(&format!("# TYPE onair_buddy_{} {}\n", name, kind));
+            metrics.push_str(&format!("onair_buddy_{} {}\n", name, value));
+        }
+        let mut metrics = String::new();
+        let mut gauge = |name: &str, help: &str, samples: &[(String, usize)]| {
+            metrics.push_str(&format!("# HELP onair_buddy_{} {}\n", name, help));
+            metrics.push_str(&format!("# TYPE onair_buddy_{} gauge\n", name));
+            for (labels, value) in samples {
+                metrics.push_str(&format!("onair_buddy_{}{} {}\n", name, labels, value));
+            }
+        };
+        gauge(
+            "on_air",
+            "Whether an in scope device is in use.",
+            &[(String::new(), self.on_air as usize)],
+        );
+        gauge(
+            "paused",
+            "Whether hooks are paused.",
+            &[(String::new(), self.paused as usize)],
+        );
+        gauge(
+            "apps",
+            "Number of distinct apps using in scope devices.",
+            &[(String::new(), self.apps)],
+        );
+        let links: Vec<(String, usize)> = self
+            .links
+            .iter()
+            .map(|link| {
+                let labels = format!(
+                    "{{id=\"{}\",device=\"{}\",target=\"{}\"}}",
+                    link.id,
+                    prometheus_escape(&link.device),
+                    prometheus_escape(&link.target)
+                );
+                (labels, 1)
+            })
+            .collect();
+        gauge("link", "Active in scope links.", &links);
+        let timings = &self.hook_timings;
+        metric(
+            &mut metrics,
+            "counter",
+            "hook_runs_total",
+            "On and off air hooks run.",
+            timings.runs,
+        );
+        metric(
+            &mut metrics,
+            "gauge",
+            "hook_duration_seconds_avg",
+            "Average time the on and off air hooks took.",
+            timings.average().as_secs_f64(),
+        );
+        metric(
+            &mut metrics,
+            "gauge",
+            "hook_duration_seconds_max",
+            "Longest time an on or off air hook took.",
+            timings.max.as_secs_f64(),
+        );
+        metric(
+            &mut metrics,
+            "counter",
+            "transitions_total",
+            "Transitions to on and off air.",
+            self.transitions,
+        );
+        metric(
+            &mut metrics,
+            "counter",
+            "hook_failures_total",
+            "Actor invocations that returned an error.",
+            self.counters.hook_failures,
+        );
+        metric(
+            &mut metrics,
+            "counter",
+            "malformed_links_total",
+            "Links skipped because they lacked node ids.",
+            self.counters.malformed_links,
+        );
+        metric(
+            &mut metrics,
+            "counter",
+            "parse_errors_total",
+            "Links skipped because their node ids couldn't be parsed.",
+            self.counters.parse_errors,
+        );
+        metric(
+            &mut metrics,
+            "counter",
+            "link_latency_devices_total",
+            "In scope devices whose first link became active.",
+            self.link_latency.count,
+        );
+        if let Some(average) = self.link_latency.average() {
+            metric(
+                &mut metrics,
+                "gauge",
+                "link_latency_seconds_avg",
+                "Average time from an in scope device showing up to its first active link.",
+                average.as_secs_f64(),
+            );
+        }
+        metrics.trim_end().to_string()
+    }
+}
+
+/// Label values escape backslashes, quotes and newlines.
+fn prometheus_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn json_string(value: &str) -> String {
+    format!("\"{}\"", json_escape(value))
+}
+
+/// Formats the duration like `1h 2m 3s`, dropping fractions of a second.
+pub fn format_whole_seconds(duration: Duration) -> String {
+    let duration = Duration::from_secs(duration.as_secs());
+    humantime_serde::re::humantime::format_duration(duration).to_string()
+}
+
+/// Escapes the value for use within a JSON string literal.
+pub fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Access to the status of a watcher from other threads.
+pub trait StatusSource: Send + Sync {
+    fn status(&self) -> Status;
+}
+
+impl<T: OnAirActor> StatusSource for RwLock<State<T>> {
+    fn status(&self) -> Status {
+        self.read().unwrap().status()
     }
 }
+
+/// Commands that can be sent to a running watcher from other threads.
+pub enum WatcherCommand {
+    Pause,
+    Resume,
+    Quit,
+    /// Adds a manual link from the named device, as if an app started using it.
+    ForceLink(String),
+    /// Removes the manual links of the named device, or all of them.
+    ClearForcedLinks(Option<String>),
+    /// Goes off air regardless of the current links, until the next event.
+    Reset,
+    /// Keeps the watcher on air regardless of the links while set, see [`crate::flag_file`].
+    SetForced(bool),
+    /// Brings replaced actors up to date with the current state, see [`State::replay`].
+    Replay,
+    /// Swaps in reloaded `devices_in_scope` and `devices_ignored` lists, see
+    /// [`State::set_device_lists`].
+    SetDeviceLists {
+        devices_in_scope: HashSet<String>,
+        devices_ignored: HashSet<String>,
+    },
+}
+
 pub struct RecordingWatcher<T>
 where
     T: OnAirActor,
 {
     state: Arc<RwLock<State<T>>>,
+    remote: Option<String>,
+    require_active_links: bool,
+    scope_default_source: bool,
+    empty_graph_retry: Option<Duration>,
+    presence_timeout: Option<Duration>,
+    event_log: Option<Arc<EventLog>>,
 }
 
 impl<T: OnAirActor + 'static> RecordingWatcher<T> {
-    pub fn new(
-        devices_in_scope: HashSet<String>,
-        devices_ignored: HashSet<String>,
-        actor: T,
-    ) -> Self {
+    pub fn new(config: &Config, actor: T) -> Self {
         RecordingWatcher {
-            state: Arc::new(RwLock::new(State::new(
-                devices_in_scope,
-                devices_ignored,
-                actor,
-            ))),
+            state: Arc::new(RwLock::new(State::new(config, actor))),
+            remote: config.remote.clone(),
+            require_active_links: config.require_active_links,
+            scope_default_source: config.scope_default_source,
+            empty_graph_retry: config.empty_graph_retry,
+            presence_timeout: config.presence_timeout,
+            event_log: None,
+        }
+    }
+
+    /// Calls `observer` on every transition, after the actor. Like the actor, observers aren't
+    /// called while the hooks are held back, e.g. while paused.
+    pub fn on_change(&mut self, observer: impl Fn(TransitionEvent) + Send + Sync + 'static) {
+        self.state.write().unwrap().observers.0.push(Box::new(observer));
+    }
+
+    /// Writes all registry events of the following sessions to the log, see [`crate::event_log`].
+    pub fn record_events(&mut self, log: EventLog) {
+        self.event_log = Some(Arc::new(log));
+    }
+
+    pub fn status_source(&self) -> Arc<dyn StatusSource> {
+        self.state.clone()
+    }
+
+    pub fn start_watcher(
+        &mut self,
+        mut commands: Receiver<WatcherCommand>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            let mainloop = MainLoop::new()?;
+            let session = self.connect(&mainloop, commands)?;
+            mainloop.run();
+            match session.into_retry() {
+                Some(receiver) => commands = self.wait_for_retry(receiver),
+                None => break,
+            }
+        }
+        info!("Shutting down..");
+        self.state.write().unwrap().shutdown();
+        Ok(())
+    }
+
+    /// Same as [`Self::start_watcher`], but drives the PipeWire loop from a Tokio runtime by
+    /// waiting for its file descriptor to become readable instead of blocking the thread, and
+    /// waits with a Tokio timer before connecting again.
+    ///
+    /// PipeWire objects must stay on the thread that created them, so the returned future is
+    /// not `Send` and needs to run on a current thread runtime or a `tokio::task::LocalSet`.
+    /// Hooks still run on that thread and hold up its other tasks for up to `command_timeout`.
+    #[cfg(feature = "tokio")]
+    pub async fn start_watcher_async(
+        &mut self,
+        mut commands: Receiver<WatcherCommand>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use tokio::io::unix::AsyncFd;
+        use tokio::io::Interest;
+
+        loop {
+            let mainloop = MainLoop::new()?;
+            let session = self.connect(&mainloop, commands)?;
+            let loop_fd = AsyncFd::with_interest(mainloop.fd(), Interest::READABLE)?;
+            while !session.quit.get() && !session.retry.get() {
+                let mut ready = loop_fd.readable().await?;
+                while mainloop.iterate(Duration::ZERO) > 0 {}
+                ready.clear_ready();
+            }
+            match session.into_retry() {
+                Some(receiver) => {
+                    let delay = self.empty_graph_retry.unwrap_or_default();
+                    info!("Connecting again in [{:?}]", delay);
+                    tokio::time::sleep(delay).await;
+                    commands = receiver;
+                }
+                None => break,
+            }
         }
+        info!("Shutting down..");
+        self.state.write().unwrap().shutdown();
+        Ok(())
     }
 
-    pub fn start_watcher(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn wait_for_retry(&self, commands: Receiver<WatcherCommand>) -> Receiver<WatcherCommand> {
+        let delay = self.empty_graph_retry.unwrap_or_default();
+        info!("Connecting again in [{:?}]", delay);
+        thread::sleep(delay);
+        commands
+    }
+
+    /// Connects to PipeWire and registers all listeners on the given loop, which then needs to
+    /// be run by the caller for as long as the returned session is alive.
+    fn connect<'l>(
+        &self,
+        mainloop: &'l MainLoop,
+        commands: Receiver<WatcherCommand>,
+    ) -> Result<Session<'l>, Box<dyn std::error::Error>> {
         //let local_registry: Arc<RwLock<HashMap<u32, String>>> = Arc::new(RwLock::new(HashMap::new()));
         //let state: Arc<RwLock<State>> = Arc::new(RwLock::new(State::default()));
         let global_state = self.state.clone();
         let remove_state = self.state.clone();
 
-        let mainloop = MainLoop::new()?;
-        let context = Context::new(&mainloop)?;
-        let core = context.connect(None)?;
-        let registry = core.get_registry()?;
+        let context = Context::new(mainloop)?;
+        let core = connect_core(&context, self.remote.as_deref())?;
+        let registry = Rc::new(core.get_registry()?);
+        let global_registry = Rc::downgrade(&registry);
+        let require_active_links = self.require_active_links;
+        // link proxies and their listeners, only bound if link states are watched
+        let bound_links: Rc<RefCell<HashMap<u32, (Link, LinkListener)>>> = Rc::default();
+        let remove_links = bound_links.clone();
+        // node proxies and their listeners, only bound for nodes whose properties are followed
+        let bound_nodes: Rc<RefCell<HashMap<u32, (Node, NodeListener)>>> = Rc::default();
+        let remove_nodes = bound_nodes.clone();
+        let scope_default_source = self.scope_default_source;
+        // the bound default metadata, only if the default source is watched
+        let bound_metadata: Rc<RefCell<HashMap<u32, (Metadata, MetadataListener)>>> =
+            Rc::default();
+        let remove_metadata = bound_metadata.clone();
+        let global_log = self.event_log.clone();
+        let remove_log = self.event_log.clone();
+
+        let presence = Rc::new(Presence::new());
+        let global_presence = presence.clone();
+        let remove_presence = presence.clone();
+        let done_presence = presence.clone();
+        let retry = Rc::new(Cell::new(false));
+
+        let tick_state = self.state.clone();
+        let tick_core = core.clone();
+        let tick_retry = retry.clone();
+        let tick_loop = mainloop.downgrade();
+        let presence_timeout = self.presence_timeout;
+        let timer = mainloop.add_timer(move |_| {
+            let mut state = tick_state.write().unwrap();
+            state.tick();
+            let Some(timeout) = presence_timeout else {
+                return;
+            };
+            if !state.check_if_on_air() {
+                // only time spent on air counts
+                presence.touch();
+                return;
+            }
+            let silent_for = presence.last_event.get().elapsed();
+            if silent_for >= timeout {
+                warn!(
+                    "No events from PipeWire for [{:?}] while on air, going off air and \
+                     connecting again",
+                    silent_for
+                );
+                state.forget_links();
+                tick_retry.set(true);
+                if let Some(mainloop) = tick_loop.upgrade() {
+                    mainloop.quit();
+                }
+            } else if silent_for >= timeout / 2 && !presence.ping_sent.replace(true) {
+                // a healthy connection answers, which counts as an event
+                debug!("no events for [{:?}], pinging PipeWire", silent_for);
+                if let Err(e) = tick_core.sync(0) {
+                    debug!("failed to ping PipeWire: {}", e);
+                }
+            }
+        });
+        timer
+            .update_timer(Some(TICK_INTERVAL), Some(TICK_INTERVAL))
+            .into_result()?;
+
+        let quit = Rc::new(Cell::new(false));
+        let command_quit = quit.clone();
+        let command_state = self.state.clone();
+        let command_loop = mainloop.downgrade();
+        let commands = commands.attach(mainloop, move |command| match command {
+            WatcherCommand::Pause => command_state.write().unwrap().set_paused(true),
+            WatcherCommand::Resume => command_state.write().unwrap().set_paused(false),
+            WatcherCommand::ForceLink(device) => command_state.write().unwrap().force_link(&device),
+            WatcherCommand::ClearForcedLinks(device) => command_state
+                .write()
+                .unwrap()
+                .clear_forced_links(device.as_deref()),
+            WatcherCommand::Reset => command_state.write().unwrap().reset(),
+            WatcherCommand::SetForced(forced) => command_state.write().unwrap().set_forced(forced),
+            WatcherCommand::Replay => command_state.write().unwrap().replay(),
+            WatcherCommand::SetDeviceLists {
+                devices_in_scope,
+                devices_ignored,
+            } => command_state
+                .write()
+                .unwrap()
+                .set_device_lists(devices_in_scope, devices_ignored),
+            WatcherCommand::Quit => {
+                command_quit.set(true);
+                if let Some(mainloop) = command_loop.upgrade() {
+                    mainloop.quit();
+                }
+            }
+        });
+        let signals = [Signal::SIGINT, Signal::SIGTERM]
+            .into_iter()
+            .map(|signal| quit_on_signal(mainloop, signal, &quit))
+            .collect();
 
-        let _listener = registry
+        let listener = registry
             .add_listener_local()
             .global(move |global| {
+                global_presence.touch();
+                if let (Some(log), Some(props)) = (&global_log, &global.props) {
+                    if let Some(type_) = recorded_type(&global.type_) {
+                        log.global(global.id, type_, props);
+                    }
+                }
                 match global.type_ {
                     ObjectType::Node => {
                         if let Some(node_props) = &global.props {
@@ -88,117 +1082,1141 @@ impl<T: OnAirActor + 'static> RecordingWatcher<T> {
                                 .unwrap()
                                 .add_node(global.id, node_props);
                         };
+                        let watched = global_state.read().unwrap().watches_node_info(global.id);
+                        if watched {
+                            if let Some(registry) = global_registry.upgrade() {
+                                let state = global_state.clone();
+                                let log = global_log.clone();
+                                match watch_node_info(&registry, global, state, log) {
+                                    Ok(bound) => {
+                                        bound_nodes.borrow_mut().insert(global.id, bound);
+                                    }
+                                    Err(e) => warn!(
+                                        "failed to watch info of node [{}]: {}",
+                                        global.id, e
+                                    ),
+                                }
+                            }
+                        }
                         debug!("done with node [{}]", global.id);
                     }
 
+                    ObjectType::Port => {
+                        if let Some(port_props) = &global.props {
+                            global_state
+                                .write()
+                                .unwrap()
+                                .add_port(global.id, port_props);
+                        }
+                    }
+
+                    ObjectType::Device => {
+                        if let Some(device_props) = &global.props {
+                            global_state
+                                .write()
+                                .unwrap()
+                                .add_device(global.id, device_props);
+                        }
+                    }
+
                     ObjectType::Link => {
                         if let Some(link_props) = &global.props {
-                            global_state
+                            let in_scope = global_state
                                 .clone()
                                 .write()
                                 .unwrap()
                                 .add_link(&global.id, link_props);
+                            if in_scope && require_active_links {
+                                if let Some(registry) = global_registry.upgrade() {
+                                    let state = global_state.clone();
+                                    let log = global_log.clone();
+                                    match watch_link_state(&registry, global, state, log) {
+                                        Ok(bound) => {
+                                            bound_links.borrow_mut().insert(global.id, bound);
+                                        }
+                                        Err(e) => warn!(
+                                            "failed to watch state of link [{}]: {}",
+                                            global.id, e
+                                        ),
+                                    }
+                                }
+                            }
                         }
                         debug!("done with link [{}]", global.id);
                     }
-                    _ => {
 
-                        //println!("[{}]-{:?}", global.id, global.props);
+                    ObjectType::Metadata => {
+                        let is_default = global.props.as_ref().and_then(|props| {
+                            props.get("metadata.name").map(|name| name == "default")
+                        });
+                        if scope_default_source && is_default == Some(true) {
+                            if let Some(registry) = global_registry.upgrade() {
+                                let state = global_state.clone();
+                                let log = global_log.clone();
+                                match watch_default_source(&registry, global, state, log) {
+                                    Ok(bound) => {
+                                        bound_metadata.borrow_mut().insert(global.id, bound);
+                                    }
+                                    Err(e) => warn!(
+                                        "failed to watch default metadata [{}]: {}",
+                                        global.id, e
+                                    ),
+                                }
+                            }
+                        }
                     }
+                    _ => {}
                 };
             })
             .global_remove(move |id| {
-                if remove_state
-                    .clone()
-                    .read()
-                    .unwrap()
-                    .active_links
-                    .contains(&id)
-                {
-                    info!("In scope link [{}] removed.", id);
-                    remove_state.clone().write().unwrap().remove_link(&id);
-                    info!(
-                        "On Air: [{:?}]",
-                        remove_state.clone().read().unwrap().check_if_on_air()
-                    );
+                remove_presence.touch();
+                if let Some(log) = &remove_log {
+                    log.remove(id);
                 }
+                remove_links.borrow_mut().remove(&id);
+                remove_nodes.borrow_mut().remove(&id);
+                remove_metadata.borrow_mut().remove(&id);
+                remove_state.write().unwrap().remove_global(&id);
             })
             .register();
-        mainloop.run();
-        Ok(())
-    }
-}
 
-struct State<T> where T: OnAirActor {
-    devices_in_scope: HashSet<String>,
-    devices_ignored: HashSet<String>,
-    ids_in_scope: HashSet<u32>,
-    ids_ignored: HashSet<u32>,
-    active_links: HashSet<u32>,
-    on_air: bool,
-    registry: HashMap<u32, String>,
-    actor: T,
-}
+        // answers to the pings count as events too
+        let presence_listener = core
+            .add_listener_local()
+            .done(move |_, _| done_presence.touch())
+            .register();
 
-impl<T> State<T> where T:OnAirActor{
-    pub fn new(
-        devices_in_scope: HashSet<String>,
-        devices_ignored: HashSet<String>,
-        actor: T,
-    ) -> Self {
+        self.state.write().unwrap().initialized = false;
+        let done_retry = retry.clone();
+        let done_state = self.state.clone();
+        let done_loop = mainloop.downgrade();
+        let empty_graph_retry = self.empty_graph_retry;
+        let done_log = self.event_log.clone();
+        let core_listener = when_enumerated(&core, move || {
+            if let Some(log) = &done_log {
+                log.enumerated();
+            }
+            let empty = done_state.write().unwrap().finish_enumeration();
+            if empty && empty_graph_retry.is_some() {
+                done_retry.set(true);
+                if let Some(mainloop) = done_loop.upgrade() {
+                    mainloop.quit();
+                }
+            }
+        })?;
+
+        self.state.write().unwrap().startup();
+        Ok(Session {
+            commands,
+            _timer: timer,
+            _signals: signals,
+            _listener: listener,
+            _core_listener: core_listener,
+            _presence_listener: presence_listener,
+            _registry: registry,
+            _core: core,
+            _context: context,
+            quit,
+            retry,
+        })
+    }
+
+    /// Feeds scripted events through the same state handling and actors as the real watcher.
+    pub fn run_simulation(&mut self, events: Vec<TimedEvent>) {
+        pipewire::init();
+        self.state.write().unwrap().startup();
+        for TimedEvent { delay, event } in events {
+            // the events before the first delay make up the initial graph
+            if !delay.is_zero() {
+                self.state.write().unwrap().finish_enumeration();
+            }
+            self.wait_until(Instant::now() + delay);
+            let mut state = self.state.write().unwrap();
+            match event {
+                SimulatedEvent::NodeAdded {
+                    id,
+                    name,
+                    app,
+                    media_class,
+                    role,
+                } => {
+                    debug!("simulating node [{}] named [{}]", id, name);
+                    let mut props = properties! { *keys::NODE_DESCRIPTION => name };
+                    if let Some(app) = app {
+                        props.insert(*keys::APP_NAME, app);
+                    }
+                    if let Some(media_class) = media_class {
+                        props.insert(*keys::MEDIA_CLASS, media_class);
+                    }
+                    if let Some(role) = role {
+                        props.insert(*keys::MEDIA_ROLE, role);
+                    }
+                    state.add_node(id, &props);
+                }
+                SimulatedEvent::NodeRemoved { id } => {
+                    state.remove_node(&id);
+                }
+                SimulatedEvent::LinkAdded {
+                    id,
+                    output_node,
+                    input_node,
+                } => {
+                    debug!("simulating link [{}]", id);
+                    state.add_link(
+                        &id,
+                        &properties! {
+                            *keys::LINK_OUTPUT_NODE => output_node.to_string(),
+                            *keys::LINK_INPUT_NODE => input_node.to_string(),
+                        },
+                    );
+                }
+                SimulatedEvent::LinkStateChanged { id, active } => {
+                    state.set_link_active(&id, active);
+                }
+                SimulatedEvent::LinkRemoved { id } => {
+                    state.remove_link_if_present(&id);
+                }
+            }
+        }
+        let mut state = self.state.write().unwrap();
+        state.finish_enumeration();
+        state.shutdown();
+    }
+
+    /// Feeds a recorded session through the same state handling as the live watcher, with
+    /// the original timing.
+    pub fn run_replay(&mut self, events: Vec<TimedLoggedEvent>) {
+        pipewire::init();
+        self.state.write().unwrap().startup();
+        let started = Instant::now();
+        for TimedLoggedEvent { at, event } in events {
+            self.wait_until(started + at);
+            let mut state = self.state.write().unwrap();
+            match event {
+                LoggedEvent::Global { id, type_, props } => {
+                    debug!("replaying [{}] [{}]", type_, id);
+                    let dict = to_properties(&props);
+                    match type_.as_str() {
+                        "Node" => state.add_node(id, &dict),
+                        "Port" => state.add_port(id, &dict),
+                        "Device" => state.add_device(id, &dict),
+                        "Link" => {
+                            state.add_link(&id, &dict);
+                        }
+                        other => debug!("skipping recorded global of type [{}]", other),
+                    }
+                }
+                LoggedEvent::Remove { id } => state.remove_global(&id),
+                LoggedEvent::LinkState { id, active } => state.set_link_active(&id, active),
+                LoggedEvent::NodeInfo { id, props } => {
+                    state.update_node_info(id, &to_properties(&props));
+                }
+                LoggedEvent::Enumerated => {
+                    state.finish_enumeration();
+                }
+                LoggedEvent::DefaultSource { name } if self.scope_default_source => {
+                    state.set_default_source(name);
+                }
+                LoggedEvent::DefaultSource { .. } => {}
+            }
+        }
+        self.state.write().unwrap().shutdown();
+    }
+
+    /// Sleeps until the given time, advancing time based state in between.
+    fn wait_until(&self, resume_at: Instant) {
+        while let Some(remaining) = resume_at.checked_duration_since(Instant::now()) {
+            thread::sleep(remaining.min(TICK_INTERVAL));
+            self.state.write().unwrap().tick();
+        }
+    }
+}
+
+/// Name of the object type in event logs, for the types the watcher handles.
+fn recorded_type(type_: &ObjectType) -> Option<&'static str> {
+    match type_ {
+        ObjectType::Node => Some("Node"),
+        ObjectType::Port => Some("Port"),
+        ObjectType::Device => Some("Device"),
+        ObjectType::Link => Some("Link"),
+        _ => None,
+    }
+}
+
+/// Connects to the given remote, or the default one for `None`.
+pub fn connect_core(context: &Context<MainLoop>, remote: Option<&str>) -> Result<Core, Error> {
+    context
+        .connect(remote_properties(remote))
+        .context(PipeWireUnavailableSnafu {
+            remote: remote.unwrap_or("default"),
+        })
+}
+
+/// Calls `enumerated` once the registry has announced all objects that exist now, which is
+/// when the server answers a sync. The returned listener has to be kept until then.
+pub fn when_enumerated(
+    core: &Core,
+    enumerated: impl Fn() + 'static,
+) -> Result<pipewire::Listener, pipewire::Error> {
+    let pending = core.sync(0)?;
+    Ok(core
+        .add_listener_local()
+        .done(move |id, seq| {
+            if id == PW_ID_CORE && seq == pending {
+                enumerated();
+            }
+        })
+        .register())
+}
+
+/// Runs the loop until the registry has announced all objects, for one-off listings.
+pub fn run_until_enumerated(mainloop: &MainLoop, core: &Core) -> Result<(), pipewire::Error> {
+    let done_loop = mainloop.downgrade();
+    let _listener = when_enumerated(core, move || {
+        if let Some(mainloop) = done_loop.upgrade() {
+            mainloop.quit();
+        }
+    })?;
+    mainloop.run();
+    Ok(())
+}
+
+/// Connection properties selecting the given remote, `None` connects to the default one.
+pub fn remote_properties(remote: Option<&str>) -> Option<Properties> {
+    remote.map(|remote| {
+        info!("Connecting to PipeWire remote [{}]", remote);
+        properties! { *keys::REMOTE_NAME => remote }
+    })
+}
+
+/// Binds the link to follow its state, it only counts as on air while it is active.
+fn watch_link_state<T: OnAirActor + 'static>(
+    registry: &Registry,
+    global: &GlobalObject<ForeignDict>,
+    state: Arc<RwLock<State<T>>>,
+    log: Option<Arc<EventLog>>,
+) -> Result<(Link, LinkListener), pipewire::Error> {
+    let id = global.id;
+    let link: Link = registry.bind(global)?;
+    let listener = link
+        .add_listener_local()
+        .info(move |info| {
+            let active = matches!(info.state(), LinkState::Active);
+            debug!("link [{}] is in state [{:?}]", id, info.state());
+            if let Some(log) = &log {
+                log.link_state(id, active);
+            }
+            state.write().unwrap().set_link_active(&id, active);
+        })
+        .register();
+    Ok((link, listener))
+}
+
+/// Binds the node to follow its properties, for live streams and nodes renaming themselves.
+fn watch_node_info<T: OnAirActor + 'static>(
+    registry: &Registry,
+    global: &GlobalObject<ForeignDict>,
+    state: Arc<RwLock<State<T>>>,
+    log: Option<Arc<EventLog>>,
+) -> Result<(Node, NodeListener), pipewire::Error> {
+    let id = global.id;
+    let node: Node = registry.bind(global)?;
+    let listener = node
+        .add_listener_local()
+        .info(move |info| {
+            let Some(props) = info.props() else {
+                return;
+            };
+            if let Some(log) = &log {
+                log.node_info(id, props);
+            }
+            state.write().unwrap().update_node_info(id, props);
+        })
+        .register();
+    Ok((node, listener))
+}
+
+/// Binds the default metadata to follow which node is the default source.
+fn watch_default_source<T: OnAirActor + 'static>(
+    registry: &Registry,
+    global: &GlobalObject<ForeignDict>,
+    state: Arc<RwLock<State<T>>>,
+    log: Option<Arc<EventLog>>,
+) -> Result<(Metadata, MetadataListener), pipewire::Error> {
+    let metadata: Metadata = registry.bind(global)?;
+    let listener = metadata
+        .add_listener_local()
+        .property(move |_subject, key, _type, value| {
+            // no key means all properties were cleared
+            if key.is_none_or(|key| key == DEFAULT_SOURCE_KEY) {
+                let name = value.and_then(default_source_name);
+                if let Some(log) = &log {
+                    log.default_source(name.as_deref());
+                }
+                state.write().unwrap().set_default_source(name);
+            }
+            0
+        })
+        .register();
+    Ok((metadata, listener))
+}
+
+/// When PipeWire was last heard from, see `presence_timeout`.
+struct Presence {
+    last_event: Cell<Instant>,
+    /// Set once a ping was sent after a quiet period, until the next event.
+    ping_sent: Cell<bool>,
+}
+
+impl Presence {
+    fn new() -> Self {
+        Presence {
+            last_event: Cell::new(Instant::now()),
+            ping_sent: Cell::new(false),
+        }
+    }
+
+    fn touch(&self) {
+        self.last_event.set(Instant::now());
+        self.ping_sent.set(false);
+    }
+}
+
+/// Everything that has to stay alive while connected. Fields are dropped in declaration
+/// order, listeners go away before the objects they are registered on.
+struct Session<'l> {
+    commands: AttachedReceiver<'l, WatcherCommand>,
+    _timer: TimerSource<'l>,
+    _signals: Vec<SignalSource<'l>>,
+    _listener: registry::Listener,
+    _core_listener: pipewire::Listener,
+    _presence_listener: pipewire::Listener,
+    _registry: Rc<Registry>,
+    _core: Core,
+    _context: Context<MainLoop>,
+    /// Set once the watcher should stop, for loops that aren't run by PipeWire.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    quit: Rc<Cell<bool>>,
+    /// Set if the graph was empty and the connection should be retried.
+    retry: Rc<Cell<bool>>,
+}
+
+/// Ends the session like the `quit` command, so the shutdown hooks also run when stopped by
+/// Ctrl+C or systemd.
+fn quit_on_signal<'l>(
+    mainloop: &'l MainLoop,
+    signal: Signal,
+    quit: &Rc<Cell<bool>>,
+) -> SignalSource<'l> {
+    let quit = quit.clone();
+    let weak_loop = mainloop.downgrade();
+    mainloop.add_signal_local(signal, move || {
+        info!("Received [{:?}]", signal);
+        quit.set(true);
+        if let Some(mainloop) = weak_loop.upgrade() {
+            mainloop.quit();
+        }
+    })
+}
+
+impl Session<'_> {
+    /// Tears down the session, handing back the command receiver if it should be retried.
+    fn into_retry(self) -> Option<Receiver<WatcherCommand>> {
+        self.retry.get().then(|| self.commands.deattach())
+    }
+}
+
+struct State<T> where T: OnAirActor {
+    devices_in_scope: HashSet<String>,
+    /// Entries of `devices_in_scope` that matched a node or device at least once.
+    matched_entries: HashSet<String>,
+    devices_ignored: HashSet<String>,
+    /// Device name patterns in order of preference, see [`Self::apply_device_priority`].
+    device_priority: Vec<String>,
+    /// Present nodes matching a `device_priority` entry, mapped to the index of that entry.
+    priority_nodes: HashMap<u32, usize>,
+    scope_all_sources: bool,
+    include_video: bool,
+    ignore_monitors: bool,
+    require_source_class: bool,
+    count_playback_links: bool,
+    /// `media.class` of the nodes that announced one, for telling link directions apart.
+    node_classes: HashMap<u32, String>,
+    ids_in_scope: HashSet<u32>,
+    ids_ignored: HashSet<u32>,
+    scopes: Vec<ScopeConfig>,
+    link_rules: Vec<LinkRule>,
+    /// Nodes matching the devices of app specific scopes, mapped to the indices of those scopes.
+    scoped_nodes: HashMap<u32, Vec<usize>>,
+    /// App names of nodes that have one, see [`get_app_name`].
+    node_apps: HashMap<u32, String>,
+    /// `media.role` patterns of nodes whose incoming links count regardless of the device.
+    roles_in_scope: HashSet<String>,
+    /// `media.role` of nodes that have one, only tracked if roles are in scope.
+    node_roles: HashMap<u32, String>,
+    unknown_app_policy: UnknownAppPolicy,
+    /// In scope links mapped to their output node.
+    active_links: HashMap<u32, ScopedLink>,
+    /// In scope links that aren't active yet, only used if link states are watched.
+    inactive_links: HashMap<u32, ScopedLink>,
+    require_active_links: bool,
+    require_live_streams: bool,
+    /// Nodes whose latest properties mark them as live streams, see [`is_live_stream`].
+    live_nodes: HashSet<u32>,
+    /// Nodes put in scope by matching a name in `devices_in_scope`, followed as they rename.
+    named_nodes: HashSet<u32>,
+    scope_default_source: bool,
+    /// `node.name` of the default source as announced in the default metadata.
+    default_source: Option<String>,
+    /// The default source node, if it was put in scope for being the default.
+    default_source_node: Option<u32>,
+    /// `node.name` of every node, only kept with `scope_default_source`.
+    node_names: HashMap<u32, String>,
+    require_audio_ports: bool,
+    /// `format.dsp` of all ports, only tracked if audio ports are required.
+    port_formats: HashMap<u32, String>,
+    labels: BTreeMap<String, String>,
+    min_active_links: usize,
+    link_count_scope: LinkCountScope,
+    /// Links only count once they existed for this long.
+    min_link_age: Duration,
+    invert: bool,
+    /// Whether to switch the indicator off when exiting normally.
+    run_off_air_on_shutdown: bool,
+    smoothing: Option<SmoothingConfig>,
+    /// Smoothed link presence between 0 and 1, only used with smoothing.
+    activity: f64,
+    /// Number of distinct apps linked to in scope devices, see [`Self::update_level`].
+    level: usize,
+    on_air: bool,
+    paused: bool,
+    /// Set through the flag file, counts as on air on top of the links.
+    forced_on_air: bool,
+    active_hours: Vec<HoursWindow>,
+    /// Whether the local time is outside of `active_hours`, hooks are held back then.
+    outside_hours: bool,
+    on_air_delay: Duration,
+    pending_on_air_since: Option<Instant>,
+    coalesce_window: Duration,
+    /// End of the current burst of events, evaluated once it has passed.
+    coalesce_until: Option<Instant>,
+    min_on_dwell: Duration,
+    min_off_dwell: Duration,
+    /// When the last transition happened, unset before the first one.
+    state_changed_at: Option<Instant>,
+    /// Whether a transition is held back until the current state was kept long enough.
+    dwell_pending: bool,
+    /// Set by [`Self::reset`] until the next event, so ticks don't go back on air right away.
+    reset_held: bool,
+    on_air_warning: Option<WarningConfig>,
+    /// Start of the current on air period, for the on air warning.
+    on_air_since: Option<Instant>,
+    warnings_sent: u32,
+    fan_out: BTreeMap<String, FanOutConfig>,
+    /// Devices whose number of links is outside of the expected range, by name.
+    fan_out_alarms: HashSet<String>,
+    registry: HashMap<u32, String>,
+    /// Next id for manual links and their devices, counting down from [`MANUAL_TARGET`].
+    next_manual_id: u32,
+    /// Number of nodes announced, including ones that aren't in scope.
+    nodes_seen: usize,
+    /// Whether PipeWire has announced all objects that existed when connecting, everything
+    /// announced before is the initial enumeration rather than a live event.
+    initialized: bool,
+    started: bool,
+    malformed_link_policy: MalformedLinkPolicy,
+    seen_malformed_links: HashSet<String>,
+    counters: Counters,
+    hook_timings: HookTimings,
+    sessions: SessionStats,
+    /// Whether to log [`Self::sessions`] when exiting.
+    session_summary: bool,
+    /// When in scope nodes were classified, until their first link becomes active.
+    classified_at: HashMap<u32, Instant>,
+    /// Names of all devices by id, for `device:` scope entries.
+    device_names: HashMap<u32, Vec<String>>,
+    /// Capture nodes mapped to the device they belong to.
+    source_devices: HashMap<u32, u32>,
+    /// Scope decisions by [`classification_key`], kept when connecting again so nodes are
+    /// classified as soon as they are announced.
+    classifications: HashMap<String, Classification>,
+    /// Present nodes mapped to their [`classification_key`].
+    node_keys: HashMap<u32, String>,
+    /// Latest properties of the present nodes, for classifying them again after a reload.
+    node_props: HashMap<u32, Vec<(String, String)>>,
+    link_latency: LinkLatency,
+    last_link_event: Option<LinkEvent>,
+    last_transition: Option<TransitionReason>,
+    transitions: u64,
+    history: VecDeque<TransitionReason>,
+    history_size: usize,
+    /// Linked devices the actors were last told about while on air.
+    reported_devices: Vec<String>,
+    /// Scopes of the last on air hook, for the off air hook once the links are gone.
+    reported_scopes: Vec<String>,
+    actor: T,
+    observers: Observers,
+}
+
+/// Whether a node was in scope or ignored, see [`State::remember_classification`].
+#[derive(Debug, Clone, Copy)]
+struct Classification {
+    in_scope: bool,
+    ignored: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScopedLink {
+    output_node: u32,
+    input_node: u32,
+    output_port: Option<u32>,
+    direction: LinkDirection,
+    added_at: Instant,
+    /// Added through the control socket rather than seen in the graph.
+    manual: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkChange {
+    Added,
+    Activated,
+    Deactivated,
+    Removed,
+}
+
+/// A change to an in scope link, with the names of both ends at the time.
+#[derive(Debug, Clone)]
+pub struct LinkEvent {
+    pub link: u32,
+    pub change: LinkChange,
+    pub device: String,
+    pub target: String,
+}
+
+/// Why the on air state last changed. Delays and smoothing mean the cause may have happened
+/// a while before the transition itself.
+#[derive(Debug, Clone)]
+pub struct TransitionReason {
+    pub at: SystemTime,
+    pub on_air: bool,
+    pub cause: Option<LinkEvent>,
+    /// In scope devices with links right after the transition.
+    pub devices: Vec<String>,
+}
+
+impl TransitionReason {
+    pub fn to_json(&self) -> String {
+        let at = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default();
+        let devices: Vec<String> = self.devices.iter().map(|d| json_string(d)).collect();
+        let cause = match &self.cause {
+            Some(event) => format!(
+                "{{\"link\": {}, \"change\": {}, \"device\": {}, \"target\": {}}}",
+                event.link,
+                json_string(&format!("{:?}", event.change)),
+                json_string(&event.device),
+                json_string(&event.target)
+            ),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"at\": {}, \"on_air\": {}, \"devices\": [{}], \"cause\": {}}}",
+            at,
+            self.on_air,
+            devices.join(", "),
+            cause
+        )
+    }
+}
+
+impl fmt::Display for TransitionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.cause {
+            Some(event) => write!(
+                f,
+                "link [{}] from [{}] to [{}] being {:?}",
+                event.link, event.device, event.target, event.change
+            ),
+            None => write!(f, "no link change"),
+        }
+    }
+}
+
+/// Time between an in scope node showing up and its first active link.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LinkLatency {
+    pub count: u32,
+    pub total: Duration,
+}
+
+impl LinkLatency {
+    fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        self.total.checked_div(self.count)
+    }
+}
+
+/// Events that were dropped or failed, so silent data loss becomes visible.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counters {
+    /// Links skipped because they lacked node ids.
+    pub malformed_links: u64,
+    /// Links skipped because their node ids couldn't be parsed.
+    pub parse_errors: u64,
+    /// Actor invocations that returned an error.
+    pub hook_failures: u64,
+}
+
+impl fmt::Display for Counters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "malformed links: {}, unparseable node ids: {}, failed hooks: {}",
+            self.malformed_links, self.parse_errors, self.hook_failures
+        )
+    }
+}
+
+/// How long the on and off air hooks took, to find slow actors.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HookTimings {
+    pub runs: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl HookTimings {
+    fn record(&mut self, took: Duration) {
+        self.runs += 1;
+        self.total += took;
+        self.max = self.max.max(took);
+    }
+
+    pub fn average(&self) -> Duration {
+        if self.runs == 0 {
+            Duration::ZERO
+        } else {
+            self.total.div_f64(self.runs as f64)
+        }
+    }
+}
+
+impl fmt::Display for HookTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} runs, average [{:?}], longest [{:?}]",
+            self.runs,
+            self.average(),
+            self.max
+        )
+    }
+}
+
+/// On air periods since startup, for the summary logged when exiting.
+#[derive(Debug, Default, Clone)]
+pub struct SessionStats {
+    pub sessions: u64,
+    pub total: Duration,
+    pub longest: Duration,
+    /// How many sessions each device started.
+    pub triggers: HashMap<String, u64>,
+}
+
+impl SessionStats {
+    fn start(&mut self, device: Option<&str>) {
+        self.sessions += 1;
+        if let Some(device) = device {
+            *self.triggers.entry(device.to_string()).or_default() += 1;
+        }
+    }
+
+    fn end(&mut self, lasted: Duration) {
+        self.total += lasted;
+        self.longest = self.longest.max(lasted);
+    }
+
+    /// Ties go to the alphabetically first device, so the summary doesn't change between runs.
+    pub fn most_frequent_trigger(&self) -> Option<&str> {
+        self.triggers
+            .iter()
+            .max_by(|(name_a, a), (name_b, b)| a.cmp(b).then(name_b.cmp(name_a)))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.sessions == 0 {
+            return write!(f, "no on air sessions");
+        }
+        write!(
+            f,
+            "{} on air sessions, [{}] in total, longest [{}], most often started by [{}]",
+            self.sessions,
+            format_whole_seconds(self.total),
+            format_whole_seconds(self.longest),
+            self.most_frequent_trigger().unwrap_or("unknown")
+        )
+    }
+}
+
+impl<T> State<T> where T:OnAirActor{
+    pub fn new(config: &Config, actor: T) -> Self {
         let mut registry: HashMap<u32, String> = HashMap::new();
         registry.insert(u32::MAX, "unresolved".to_string());
+        registry.insert(MANUAL_TARGET, "force-link".to_string());
         State {
-            devices_in_scope,
-            devices_ignored,
-            ids_in_scope: HashSet::new(),
-            ids_ignored: HashSet::new(),
-            active_links: HashSet::new(),
+            devices_in_scope: config.devices_in_scope.clone(),
+            matched_entries: HashSet::new(),
+            devices_ignored: config.devices_ignored.clone(),
+            device_priority: config.device_priority.clone(),
+            priority_nodes: HashMap::new(),
+            scope_all_sources: config.scope_all_sources,
+            include_video: config.include_video,
+            ignore_monitors: config.ignore_monitors,
+            require_source_class: config.require_source_class,
+            count_playback_links: config.count_playback_links,
+            node_classes: HashMap::new(),
+            ids_in_scope: scope_ids(&config.devices_in_scope),
+            ids_ignored: scope_ids(&config.devices_ignored),
+            scopes: config.scopes.clone(),
+            link_rules: config.link_rules.clone(),
+            scoped_nodes: HashMap::new(),
+            node_apps: HashMap::new(),
+            roles_in_scope: config.roles_in_scope.clone(),
+            node_roles: HashMap::new(),
+            unknown_app_policy: config.unknown_app_policy,
+            active_links: HashMap::new(),
+            inactive_links: HashMap::new(),
+            require_active_links: config.require_active_links,
+            require_live_streams: config.require_live_streams,
+            live_nodes: HashSet::new(),
+            named_nodes: HashSet::new(),
+            scope_default_source: config.scope_default_source,
+            default_source: None,
+            default_source_node: None,
+            node_names: HashMap::new(),
+            require_audio_ports: config.require_audio_ports,
+            port_formats: HashMap::new(),
+            labels: config.labels.clone(),
+            min_active_links: config.min_active_links.max(1),
+            link_count_scope: config.link_count_scope,
+            min_link_age: config.min_link_age,
+            invert: config.invert,
+            run_off_air_on_shutdown: config.run_off_air_on_shutdown,
+            smoothing: config.smoothing,
+            activity: 0.0,
+            level: 0,
             on_air: false,
+            paused: false,
+            forced_on_air: false,
+            active_hours: config.active_hours.clone(),
+            outside_hours: !hours::is_active(&config.active_hours, LocalTime::now()),
+            on_air_delay: config.on_air_delay,
+            pending_on_air_since: None,
+            coalesce_window: config.coalesce_window,
+            coalesce_until: None,
+            min_on_dwell: config.min_on_dwell,
+            min_off_dwell: config.min_off_dwell,
+            state_changed_at: None,
+            dwell_pending: false,
+            reset_held: false,
+            on_air_warning: config.on_air_warning,
+            on_air_since: None,
+            warnings_sent: 0,
+            fan_out: config.fan_out.clone(),
+            fan_out_alarms: HashSet::new(),
             registry,
+            next_manual_id: MANUAL_TARGET - 1,
+            nodes_seen: 0,
+            initialized: false,
+            started: false,
+            malformed_link_policy: config.malformed_links,
+            seen_malformed_links: HashSet::new(),
+            counters: Counters::default(),
+            hook_timings: HookTimings::default(),
+            sessions: SessionStats::default(),
+            session_summary: config.session_summary,
+            classified_at: HashMap::new(),
+            device_names: HashMap::new(),
+            source_devices: HashMap::new(),
+            classifications: HashMap::new(),
+            node_props: HashMap::new(),
+            node_keys: HashMap::new(),
+            link_latency: LinkLatency::default(),
+            last_link_event: None,
+            last_transition: None,
+            transitions: 0,
+            history: VecDeque::new(),
+            history_size: config.history_size,
+            reported_devices: Vec::new(),
+            reported_scopes: Vec::new(),
+            observers: Observers::default(),
             actor,
         }
     }
-    pub fn is_link_in_scope(&self, output_node: &u32) -> bool {
-        self.ids_in_scope.contains(output_node)
+
+    /// The on air predicate, combining all configured conditions. Delays are applied on top
+    /// of this by [`Self::apply_on_air`].
+    fn evaluate_on_air(&self) -> bool {
+        self.forced_on_air
+            || match &self.smoothing {
+                Some(smoothing) => self.activity >= smoothing.threshold,
+                None => self.links_present(),
+            }
+    }
+
+    fn links_present(&self) -> bool {
+        let old_enough = self
+            .active_links
+            .values()
+            .filter(|link| link.added_at.elapsed() >= self.min_link_age)
+            .filter(|link| {
+                !self.require_live_streams
+                    || link.manual
+                    || self.live_nodes.contains(&link.input_node)
+            });
+        let count = match self.link_count_scope {
+            LinkCountScope::Total => old_enough.count(),
+            LinkCountScope::PerDevice => {
+                let mut per_device: HashMap<u32, usize> = HashMap::new();
+                for link in old_enough {
+                    *per_device.entry(link.output_node).or_default() += 1;
+                }
+                per_device.into_values().max().unwrap_or_default()
+            }
+            LinkCountScope::Devices => old_enough
+                .map(|link| link.output_node)
+                .collect::<HashSet<_>>()
+                .len(),
+        };
+        count >= self.min_active_links
     }
 
+    /// Event driven evaluation, bursts of events within `coalesce_window` are evaluated once
+    /// by the first tick after the window.
     fn update_on_air(&mut self) {
+        self.reset_held = false;
+        if !self.coalesce_window.is_zero() {
+            let now = Instant::now();
+            let due = *self.coalesce_until.get_or_insert(now + self.coalesce_window);
+            if now < due {
+                return;
+            }
+            self.coalesce_until = None;
+        }
+        self.apply_on_air();
+    }
+
+    fn apply_on_air(&mut self) {
         let current_state = self.on_air;
-        let target_state = !self.active_links.is_empty();
+        let target_state = self.evaluate_on_air();
+        if target_state && !current_state && !self.on_air_delay.is_zero() {
+            // give apps time to finish their connection handshake before going on air
+            let since = *self.pending_on_air_since.get_or_insert_with(|| {
+                debug!("delaying on air by [{:?}]", self.on_air_delay);
+                Instant::now()
+            });
+            if since.elapsed() < self.on_air_delay {
+                return;
+            }
+        }
+        if current_state == target_state {
+            if std::mem::take(&mut self.dwell_pending) {
+                debug!("held back transition is no longer needed");
+            }
+        } else if let Some(remaining) = self.remaining_dwell() {
+            // protect relays and the like from being switched in quick succession
+            if !self.dwell_pending {
+                debug!(
+                    "holding back going {} air for another [{:?}]",
+                    if target_state { "on" } else { "off" },
+                    remaining
+                );
+                self.dwell_pending = true;
+            }
+            return;
+        }
+        if self.pending_on_air_since.take().is_some() && !target_state {
+            debug!("links went away during on air delay");
+        }
         if current_state != target_state {
             // states don't match, update
             info!(
                 "On Air state changed from [{}] to [{}], running hook..",
                 current_state, target_state
             );
-            self.on_air = target_state;
-            if target_state {
+            self.record_transition(target_state);
+            if self.hooks_held() {
+                info!("paused or outside active hours, not running hooks");
+            } else if self.indicator_on() {
                 info!("running on air hook");
                 self.run_on_air_hook();
             } else {
                 info!("running off air hook");
                 self.run_off_air_hook();
             }
+        } else if current_state && !self.invert && !self.hooks_held() {
+            self.run_devices_hook();
         }
     }
 
-    pub fn add_headset_id(&mut self, id: &u32) {
-        self.ids_in_scope.insert(id.clone());
-        self.update_on_air();
+    /// Switches to the given state and keeps the history, sessions and counts of it.
+    fn record_transition(&mut self, target_state: bool) {
+        self.dwell_pending = false;
+        self.state_changed_at = Some(Instant::now());
+        self.on_air = target_state;
+        let ended = std::mem::replace(&mut self.on_air_since, target_state.then(Instant::now));
+        if let Some(since) = ended {
+            self.sessions.end(since.elapsed());
+        }
+        self.warnings_sent = 0;
+        let reason = TransitionReason {
+            at: SystemTime::now(),
+            on_air: target_state,
+            cause: self.last_link_event.clone(),
+            devices: self.transition().devices,
+        };
+        info!(
+            onair = u8::from(target_state),
+            device = %reason.devices.join(", "),
+            "Transition caused by {}",
+            reason
+        );
+        if self.history_size > 0 {
+            if self.history.len() >= self.history_size {
+                self.history.pop_front();
+            }
+            self.history.push_back(reason.clone());
+        }
+        if target_state {
+            let trigger = match &reason.cause {
+                Some(cause) => Some(cause.device.as_str()),
+                None => reason.devices.first().map(String::as_str),
+            };
+            self.sessions.start(trigger);
+        }
+        self.last_transition = Some(reason);
+        self.transitions += 1;
+    }
+
+    /// How much longer the current state has to be kept, if at all.
+    fn remaining_dwell(&self) -> Option<Duration> {
+        let dwell = if self.on_air {
+            self.min_on_dwell
+        } else {
+            self.min_off_dwell
+        };
+        dwell
+            .checked_sub(self.state_changed_at?.elapsed())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Called periodically to advance time based state.
+    pub fn tick(&mut self) {
+        self.check_active_hours();
+        self.check_on_air_warning();
+        self.check_fan_out();
+        if self.reset_held {
+            return;
+        }
+        let coalesced = self.coalesce_until.is_some_and(|due| Instant::now() >= due);
+        if coalesced {
+            self.coalesce_until = None;
+        }
+        if let Some(smoothing) = &self.smoothing {
+            // exponential moving average of whether links are present
+            let input = if self.links_present() { 1.0 } else { 0.0 };
+            self.activity += smoothing.factor * (input - self.activity);
+            self.apply_on_air();
+        } else if coalesced
+            || self.pending_on_air_since.is_some()
+            || self.dwell_pending
+            || (!self.min_link_age.is_zero() && !self.active_links.is_empty())
+        {
+            // links may have become old enough to count, or a held back transition due
+            self.apply_on_air();
+        }
     }
 
-    pub fn add_link(&mut self, id: &u32, props: &ForeignDict) {
-        let input_node = u32::parse_value(get_input_node(props).unwrap()).unwrap();
-        let output_node = u32::parse_value(get_output_node(props).unwrap()).unwrap();
-        if self.ids_in_scope.contains(&output_node) {
-            if !self.ids_ignored.contains(&input_node) {
+    /// Returns whether the link is in scope.
+    pub fn add_link(&mut self, id: &u32, props: &impl Props) -> bool {
+        let (output_node, input_node) = match get_link_nodes(props) {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                self.report_malformed_link(id, &e);
+                return false;
+            }
+        };
+        if self.require_audio_ports && !self.carries_audio(props) {
+            debug!("Skipping link [{}] due to its output port not carrying audio", id);
+            return false;
+        }
+        if output_node == input_node {
+            // loopbacks within a node would otherwise keep an in scope node on air by itself
+            debug!("Skipping link [{}] from node [{}] to itself", id, output_node);
+            return false;
+        }
+        let rule = self.link_rule(props);
+        if rule == Some(LinkRuleAction::Ignore) {
+            info!("Ignoring link [{}] due to matching a link rule", id);
+            return false;
+        }
+        let direction = LinkDirection::classify(
+            self.node_classes.get(&output_node).map(String::as_str),
+            self.node_classes.get(&input_node).map(String::as_str),
+        );
+        if direction == LinkDirection::Playback
+            && !self.count_playback_links
+            && rule != Some(LinkRuleAction::Scope)
+        {
+            debug!("Skipping playback link [{}], see count_playback_links", id);
+            return false;
+        }
+        let mut in_scope = false;
+        if rule == Some(LinkRuleAction::Scope)
+            || self.ids_in_scope.contains(&output_node)
+            || self.app_in_scope(output_node, input_node)
+            || self.role_in_scope(input_node)
+        {
+            let input_name = self.resolve_node_id(&input_node);
+            if pattern::matches_any(&self.devices_ignored, input_name) {
+                info!(
+                    "Ignoring link [{}] from [{}] to [{}] due to target [{}] matching the ignore list",
+                    id, output_node, input_node, input_name
+                );
+            } else if !self.ids_ignored.contains(&input_node) {
                 info!(
                     "found in scope link [{}] from [{}] to [{}]",
                     id, output_node, input_node
                 );
                 info!("id:[{}] - {:?}", id, props);
-                self.active_links.insert(id.clone());
+                in_scope = true;
+                let link = ScopedLink {
+                    output_node,
+                    input_node,
+                    output_port: props
+                        .get(&keys::LINK_OUTPUT_PORT)
+                        .and_then(u32::parse_value),
+                    direction,
+                    added_at: Instant::now(),
+                    manual: false,
+                };
+                self.record_link_event(*id, link, LinkChange::Added);
+                if self.require_active_links {
+                    // only counts once its state is reported as active
+                    self.inactive_links.insert(*id, link);
+                } else {
+                    self.activate_link(*id, link);
+                }
             } else {
                 info!(
                     "Ignoring link [{}] from [{}] to [{}] due to node [{}] being in ignore list",
@@ -206,77 +2224,1876 @@ impl<T> State<T> where T:OnAirActor{
                 );
             }
         }
-        self.update_on_air()
+        self.update_on_air();
+        in_scope
     }
 
-    pub fn add_node(&mut self, id: u32, props: &ForeignDict) {
-        let node_names = get_all_names(props);
-        if !node_names.is_empty() { //let Some(node_name) = props.get("node.description") {
-            let primary_name = node_names.first().unwrap();
-            debug!("Processing node [{:?}]", primary_name);
-            self.registry.insert(id, primary_name.to_string());
+    /// Action of the first link rule whose patterns all match the link's properties.
+    fn link_rule(&self, props: &impl Props) -> Option<LinkRuleAction> {
+        self.link_rules
+            .iter()
+            .find(|rule| {
+                rule.properties.iter().all(|(key, value_pattern)| {
+                    props
+                        .get(key)
+                        .is_some_and(|value| pattern::matches(value_pattern, value))
+                })
+            })
+            .map(|rule| rule.action)
+    }
 
-            // Check if any name is in both lists
-            if node_names.iter().map(|name| self.devices_in_scope.contains(&name.to_string())).any(|present| present) {
-                info!(
-                    "Adding id [{}] as in scope due to matching node name [{}]",
-                    id, primary_name
-                );
-                self.ids_in_scope.insert(id);
-            }
+    /// Drops all links, e.g. before connecting again, as they are announced anew by then.
+    pub fn forget_links(&mut self) {
+        self.active_links.clear();
+        self.inactive_links.clear();
+        self.activity = 0.0;
+        self.pending_on_air_since = None;
+        self.update_level();
+        self.update_on_air();
+    }
 
-            if node_names.iter().map(|name| self.devices_ignored.contains(&name.to_string())).any(|present| present) {
-                info!(
-                    "Adding id [{}] as ignored due to matching node name [{}]",
-                    id, primary_name
-                );
-                self.ids_ignored.insert(id);
+    /// Adds an active link from a made up node with the given name, so actors can be tried
+    /// out against a running instance without an actual call.
+    pub fn force_link(&mut self, device: &str) {
+        let id = self.next_manual_id;
+        let output_node = id - 1;
+        self.next_manual_id -= 2;
+        self.registry.insert(output_node, device.to_string());
+        info!("Forcing link [{}] from [{}]", id, device);
+        let link = ScopedLink {
+            output_node,
+            input_node: MANUAL_TARGET,
+            output_port: None,
+            direction: LinkDirection::Capture,
+            added_at: Instant::now(),
+            manual: true,
+        };
+        self.record_link_event(id, link, LinkChange::Added);
+        self.activate_link(id, link);
+        self.update_on_air();
+    }
+
+    /// Removes the manual links of `device`, or all manual links if it is `None`.
+    pub fn clear_forced_links(&mut self, device: Option<&str>) {
+        let mut ids: Vec<u32> = self
+            .active_links
+            .iter()
+            .filter(|(_, link)| link.manual)
+            .filter(|(_, link)| {
+                device.is_none_or(|device| self.resolve_node_id(&link.output_node) == device)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        for id in ids {
+            if let Some(link) = self.active_links.remove(&id) {
+                info!("Removing forced link [{}]", id);
+                self.record_link_event(id, link, LinkChange::Removed);
+                self.registry.remove(&link.output_node);
             }
         }
+        self.update_level();
+        self.update_on_air();
     }
 
-    pub fn resolve_node_id(&self, id: &u32) -> &str {
-        self.registry
-            .get(id)
-            .unwrap_or_else(|| self.registry.get(&u32::MAX).unwrap())
+    /// Port formats are kept for `require_audio_ports` and to show them in the status.
+    pub fn add_port(&mut self, id: u32, props: &impl Props) {
+        if let Some(format) = props.get(&keys::FORMAT_DSP) {
+            self.port_formats.insert(id, format.to_string());
+        }
     }
 
-    pub fn remove_link(&mut self, id: &u32) {
-        self.active_links.remove(id);
-        self.update_on_air();
+    /// Ports announce their format like `32 bit float mono audio` or `8 bit raw midi`. Links
+    /// from ports that haven't been seen or have no format are given the benefit of the doubt.
+    fn carries_audio(&self, props: &impl Props) -> bool {
+        let format = props
+            .get(&keys::LINK_OUTPUT_PORT)
+            .and_then(u32::parse_value)
+            .and_then(|port| self.port_formats.get(&port));
+        format.is_none_or(|format| {
+            format.ends_with("audio") || (self.include_video && format.ends_with("video"))
+        })
     }
 
-    fn run_on_air_hook(&self) -> Result<(), Error> {
-        self.actor.go_on_air();
-        Ok(())
+    /// Whether the output node belongs to a scope that includes the app of the input node.
+    fn app_in_scope(&self, output_node: u32, input_node: u32) -> bool {
+        if !self.scoped_nodes.contains_key(&output_node) {
+            return false;
+        }
+        let Some(app) = self.node_apps.get(&input_node) else {
+            let allowed = self.unknown_app_policy == UnknownAppPolicy::Allow;
+            debug!(
+                "node [{}] has no app information, counting it as in scope: [{}]",
+                input_node, allowed
+            );
+            return allowed;
+        };
+        let scope = self
+            .matching_scopes(output_node, input_node)
+            .first()
+            .map(|index| &self.scopes[*index]);
+        if let Some(scope) = scope {
+            info!(
+                "Node [{}] is in scope [{}] due to being linked to app [{}]",
+                output_node,
+                scope.name.as_deref().unwrap_or("unnamed"),
+                app
+            );
+        }
+        scope.is_some()
     }
 
-    fn run_off_air_hook(&self) -> Result<(), Error> {
-        self.actor.go_off_air();
-        Ok(())
+    /// Indices of the app specific scopes a link between the nodes counts for.
+    fn matching_scopes(&self, output_node: u32, input_node: u32) -> Vec<usize> {
+        let (Some(scopes), Some(app)) = (
+            self.scoped_nodes.get(&output_node),
+            self.node_apps.get(&input_node),
+        ) else {
+            return Vec::new();
+        };
+        scopes
+            .iter()
+            .copied()
+            .filter(|index| pattern::matches_any(&self.scopes[*index].apps, app))
+            .collect()
     }
 
-    pub fn check_if_on_air(&self) -> bool {
-        self.on_air
+    /// Whether the input node has a `media.role` that is in scope, e.g. `Communication` for
+    /// the streams of VoIP apps.
+    fn role_in_scope(&self, input_node: u32) -> bool {
+        let Some(role) = self.node_roles.get(&input_node) else {
+            return false;
+        };
+        let in_scope = pattern::matches_any(&self.roles_in_scope, role);
+        if in_scope {
+            info!("Node [{}] is in scope due to its media role [{}]", input_node, role);
+        }
+        in_scope
     }
-}
 
-fn get_input_node(props: &ForeignDict) -> Result<&str, Error> {
-    props.get("link.input.node").context(NoInputNodeSnafu {
-        props: format!("{:?}", props),
-    })
-}
+    /// Follows whether a node is a live stream as its properties change.
+    pub fn update_node_info(&mut self, id: u32, props: &impl Props) {
+        if let Some(stored) = self.node_props.get_mut(&id) {
+            *stored = owned_props(props);
+        }
+        if self.named_nodes.contains(&id) {
+            self.update_node_names(id, props);
+        }
+        if !self.require_live_streams {
+            return;
+        }
+        let live = is_live_stream(props);
+        let changed = if live {
+            self.live_nodes.insert(id)
+        } else {
+            self.live_nodes.remove(&id)
+        };
+        if changed {
+            debug!("node [{}] is live: [{}]", id, live);
+            self.update_on_air();
+        }
+    }
 
-fn get_output_node(props: &ForeignDict) -> Result<&str, Error> {
-    props.get("link.output.node").context(NoOutputNodeSnafu {
-        props: format!("{:?}", props),
-    })
-}
+    /// Whether the node's properties need to be followed after it was added.
+    pub fn watches_node_info(&self, id: u32) -> bool {
+        self.require_live_streams || self.named_nodes.contains(&id)
+    }
 
-fn get_all_names(props: &ForeignDict) -> Vec<&str> {
-    [&keys::NODE_DESCRIPTION, &keys::NODE_NICK, &keys::NODE_NAME]
-        .into_iter()
-        .map(|prop_name| props.get(prop_name))
-        .flatten()
-        .collect()
+    /// Nodes in scope by name can rename themselves, e.g. Bluetooth headsets switching
+    /// profiles. A node no longer matching any name goes out of scope, dropping its links,
+    /// and comes back into scope once it matches again.
+    fn update_node_names(&mut self, id: u32, props: &impl Props) {
+        let names = get_all_names(props);
+        if let Some(primary_name) = names.first() {
+            if self.registry.get(&id).is_none_or(|name| name != primary_name) {
+                info!("node [{}] is now named [{}]", id, primary_name);
+                self.registry.insert(id, primary_name.to_string());
+            }
+        }
+        let matching = !self.name_entries_matching(&names).is_empty();
+        let in_scope = self.ids_in_scope.contains(&id);
+        if matching && !in_scope {
+            info!("Adding id [{}] as in scope again due to matching node name", id);
+            self.mark_in_scope(id);
+        }
+        if matching || !in_scope || self.in_scope_besides_name(id, props) {
+            return;
+        }
+        info!(
+            "Removing id [{}] from scope due to it no longer matching devices_in_scope",
+            id
+        );
+        self.ids_in_scope.remove(&id);
+        self.classified_at.remove(&id);
+        self.drop_links_of(id);
+        self.update_level();
+        self.update_on_air();
+    }
+
+    fn drop_links_of(&mut self, id: u32) {
+        let links: Vec<u32> = self
+            .active_links
+            .iter()
+            .chain(&self.inactive_links)
+            .filter(|(_, link)| link.output_node == id && !link.manual)
+            .map(|(link_id, _)| *link_id)
+            .collect();
+        for link_id in links {
+            self.inactive_links.remove(&link_id);
+            if let Some(link) = self.active_links.remove(&link_id) {
+                info!("Dropping link [{}] of node [{}]", link_id, id);
+                self.record_link_event(link_id, link, LinkChange::Removed);
+            }
+        }
+    }
+
+    /// Entries of `devices_in_scope` naming nodes rather than ids or devices that match one of
+    /// the names, with `*` wildcards like the other lists.
+    fn name_entries_matching(&self, names: &[&str]) -> Vec<String> {
+        self.devices_in_scope
+            .iter()
+            .filter(|entry| {
+                !entry.starts_with(ID_SCOPE_PREFIX) && !entry.starts_with(DEVICE_SCOPE_PREFIX)
+            })
+            .filter(|entry| names.iter().any(|name| pattern::matches(entry, name)))
+            .cloned()
+            .collect()
+    }
+
+    fn in_scope_besides_name(&mut self, id: u32, props: &impl Props) -> bool {
+        let device_matches = match self.source_devices.get(&id).copied() {
+            Some(device) => self.device_in_scope(&device),
+            None => false,
+        };
+        device_matches
+            || self.devices_in_scope.contains(&format!("{}{}", ID_SCOPE_PREFIX, id))
+            || self.priority_nodes.contains_key(&id)
+            || self.default_source_node == Some(id)
+            || (self.scope_all_sources && is_capture_device(props, self.include_video))
+    }
+
+    /// Moves an in scope link between active and inactive as its state changes.
+    pub fn set_link_active(&mut self, id: &u32, active: bool) {
+        let (from, to) = if active {
+            (&mut self.inactive_links, &mut self.active_links)
+        } else {
+            (&mut self.active_links, &mut self.inactive_links)
+        };
+        if let Some(link) = from.remove(id) {
+            let state = if active { "active" } else { "inactive" };
+            debug!("link [{}] is {}", id, state);
+            if active {
+                self.record_link_event(*id, link, LinkChange::Activated);
+                self.activate_link(*id, link);
+            } else {
+                to.insert(*id, link);
+                self.record_link_event(*id, link, LinkChange::Deactivated);
+                self.update_level();
+            }
+            self.update_on_air();
+        }
+    }
+
+    fn activate_link(&mut self, id: u32, link: ScopedLink) {
+        if let Some(since) = self.classified_at.remove(&link.output_node) {
+            let latency = since.elapsed();
+            debug!(
+                "first active link [{}] of node [{}] after [{:?}]",
+                id, link.output_node, latency
+            );
+            self.link_latency.record(latency);
+        }
+        self.active_links.insert(id, link);
+        self.update_level();
+    }
+
+    /// Counts the distinct apps among the active links. Apps are told apart by the name of the
+    /// node they link to, as some apps open several nodes with the same name.
+    fn update_level(&mut self) {
+        let mut apps: Vec<&str> = self
+            .active_links
+            .values()
+            .map(|link| self.resolve_node_id(&link.input_node))
+            .collect();
+        apps.sort();
+        apps.dedup();
+        let level = apps.len();
+        if level != self.level {
+            debug!("number of apps changed from [{}] to [{}]", self.level, level);
+            self.level = level;
+            if !self.hooks_held() {
+                self.run_level_hook(level);
+            }
+        }
+    }
+
+    /// Remembers the latest change to an in scope link as the cause of the next transition.
+    fn record_link_event(&mut self, id: u32, link: ScopedLink, change: LinkChange) {
+        self.last_link_event = Some(LinkEvent {
+            link: id,
+            change,
+            device: self.resolve_node_id(&link.output_node).to_string(),
+            target: self.resolve_node_id(&link.input_node).to_string(),
+        });
+    }
+
+    /// Links without usable node ids are skipped. Depending on the policy the first occurrence
+    /// of each kind of malformed link is reported as a warning, repeats only show up at debug.
+    fn report_malformed_link(&mut self, id: &u32, error: &Error) {
+        let pattern = match error {
+            Error::InvalidNodeId { key, value } => {
+                self.counters.parse_errors += 1;
+                format!("{}={}", key, value)
+            }
+            _ => {
+                self.counters.malformed_links += 1;
+                error.to_string()
+            }
+        };
+        if self.malformed_link_policy == MalformedLinkPolicy::WarnOnce
+            && self.seen_malformed_links.insert(pattern)
+        {
+            warn!(
+                "Skipping link [{}]: {} (further links like this are only logged at debug level)",
+                id, error
+            );
+        } else {
+            debug!("Skipping link [{}]: {}", id, error);
+        }
+    }
+
+    pub fn add_node(&mut self, id: u32, props: &impl Props) {
+        self.nodes_seen += 1;
+        self.node_props.insert(id, owned_props(props));
+        if self.require_live_streams && is_live_stream(props) {
+            self.live_nodes.insert(id);
+        }
+        if let Some(media_class) = props.get(&keys::MEDIA_CLASS) {
+            self.node_classes.insert(id, media_class.to_string());
+        }
+        if self.scope_default_source {
+            if let Some(name) = props.get(&keys::NODE_NAME) {
+                self.node_names.insert(id, name.to_string());
+                if self.default_source.as_deref() == Some(name) {
+                    self.scope_default_source_node(id);
+                }
+            }
+        }
+        let node_names = get_all_names(props);
+        if !node_names.is_empty() { //let Some(node_name) = props.get("node.description") {
+            let primary_name = node_names.first().unwrap();
+            debug!("Processing node [{:?}]", primary_name);
+            self.registry.insert(id, primary_name.to_string());
+
+            if self.ignore_monitors && is_monitor(props) {
+                debug!(
+                    "Not classifying monitor node [{}], set ignore_monitors = false to include it",
+                    id
+                );
+                return;
+            }
+
+            if let Some(key) = classification_key(props) {
+                if let Some(cached) = self.classifications.get(&key).copied() {
+                    if cached.in_scope {
+                        info!(
+                            "Adding id [{}] as in scope due to [{}] having been in scope before",
+                            id, key
+                        );
+                        self.mark_in_scope(id);
+                    }
+                    if cached.ignored {
+                        self.ids_ignored.insert(id);
+                    }
+                }
+                self.node_keys.insert(id, key);
+            }
+
+            let source_class = !self.require_source_class || is_source_class(props);
+            let matching = self.name_entries_matching(&node_names);
+            let id_entry = format!("{}{}", ID_SCOPE_PREFIX, id);
+            if self.devices_in_scope.contains(&id_entry) {
+                self.matched_entries.insert(id_entry);
+            }
+            if !matching.is_empty() {
+                self.matched_entries.extend(matching);
+                if source_class {
+                    info!(
+                        "Adding id [{}] as in scope due to matching node name [{}]",
+                        id, primary_name
+                    );
+                    self.mark_in_scope(id);
+                    self.named_nodes.insert(id);
+                } else {
+                    info!(
+                        "Not adding id [{}] as in scope despite matching node name [{}], its \
+                         media.class isn't a source, see require_source_class",
+                        id, primary_name
+                    );
+                }
+            }
+
+            if self.scope_all_sources && is_capture_device(props, self.include_video) {
+                info!("Adding id [{}] as in scope due to it being a capture device", id);
+                self.mark_in_scope(id);
+            }
+
+            if let Some(app) = get_app_name(props) {
+                self.node_apps.insert(id, app.to_string());
+            }
+
+            match props.get(&keys::MEDIA_ROLE) {
+                Some(role) if !self.roles_in_scope.is_empty() => {
+                    self.node_roles.insert(id, role.to_string());
+                }
+                _ => {
+                    self.node_roles.remove(&id);
+                }
+            }
+
+            let scopes: Vec<usize> = self
+                .scopes
+                .iter()
+                .enumerate()
+                .filter(|(_, scope)| {
+                    node_names
+                        .iter()
+                        .any(|name| pattern::matches_any(&scope.devices, name))
+                })
+                .map(|(index, _)| index)
+                .collect();
+            if !scopes.is_empty() && source_class {
+                debug!("node [{}] is in app specific scopes {:?}", id, scopes);
+                self.scoped_nodes.insert(id, scopes);
+            }
+
+            let priority = self.device_priority.iter().position(|pattern| {
+                node_names
+                    .iter()
+                    .any(|name| pattern::matches(pattern, name))
+            });
+            if let Some(priority) = priority.filter(|_| source_class) {
+                debug!("node [{}] has device priority [{}]", id, priority);
+                self.priority_nodes.insert(id, priority);
+                self.apply_device_priority();
+            }
+
+            if let Some(device) = get_source_device(props) {
+                self.source_devices.insert(id, device);
+                if self.device_in_scope(&device) {
+                    info!(
+                        "Adding id [{}] as in scope due to its device [{}] matching",
+                        id, device
+                    );
+                    self.mark_in_scope(id);
+                }
+            }
+
+            if node_names
+                .iter()
+                .any(|name| pattern::matches_any(&self.devices_ignored, name))
+            {
+                info!(
+                    "Adding id [{}] as ignored due to matching node name [{}]",
+                    id, primary_name
+                );
+                self.ids_ignored.insert(id);
+            }
+            self.remember_classification(id);
+        }
+    }
+
+    /// Caches whether the node is in scope or ignored for when it is announced again after
+    /// connecting again, when devices it depends on may not be known yet. Nodes with a device
+    /// priority aren't cached, as their scope depends on the other nodes present.
+    fn remember_classification(&mut self, id: u32) {
+        let Some(key) = self.node_keys.get(&id) else {
+            return;
+        };
+        let classification = Classification {
+            in_scope: self.ids_in_scope.contains(&id) && !self.priority_nodes.contains_key(&id),
+            ignored: self.ids_ignored.contains(&id),
+        };
+        if classification.in_scope || classification.ignored {
+            self.classifications.insert(key.clone(), classification);
+        }
+    }
+
+    /// Devices usually show up before their nodes, but nodes that were seen earlier are
+    /// classified here as well.
+    pub fn add_device(&mut self, id: u32, props: &impl Props) {
+        let names = get_device_names(props);
+        debug!("Processing device [{}] with names {:?}", id, names);
+        self.device_names.insert(id, names);
+        if self.device_in_scope(&id) {
+            let nodes: Vec<u32> = self
+                .source_devices
+                .iter()
+                .filter(|(node, device)| **device == id && !self.ids_in_scope.contains(node))
+                .map(|(node, _)| *node)
+                .collect();
+            for node in nodes {
+                info!(
+                    "Adding id [{}] as in scope due to its device [{}] matching",
+                    node, id
+                );
+                self.mark_in_scope(node);
+                self.remember_classification(node);
+            }
+        }
+    }
+
+    /// Whether any name of the device matches a `device:` scope entry.
+    fn device_in_scope(&mut self, device: &u32) -> bool {
+        let Some(names) = self.device_names.get(device) else {
+            return false;
+        };
+        let matching: Vec<String> = self
+            .devices_in_scope
+            .iter()
+            .filter(|entry| {
+                entry
+                    .strip_prefix(DEVICE_SCOPE_PREFIX)
+                    .is_some_and(|pattern| names.iter().any(|name| pattern::matches(pattern, name)))
+            })
+            .cloned()
+            .collect();
+        let in_scope = !matching.is_empty();
+        self.matched_entries.extend(matching);
+        in_scope
+    }
+
+    /// Entries of `devices_in_scope` that never matched, e.g. due to a typo or a device that
+    /// wasn't plugged in yet.
+    fn unmatched_entries(&self) -> Vec<String> {
+        let mut unmatched: Vec<String> = self
+            .devices_in_scope
+            .difference(&self.matched_entries)
+            .cloned()
+            .collect();
+        unmatched.sort();
+        unmatched
+    }
+
+    /// Only the present nodes matching the earliest `device_priority` entry are in scope, the
+    /// others are fallbacks that come into scope once all preferred nodes are gone. Links only
+    /// count if they are added while their node is in scope, existing links keep counting when
+    /// a preferred node shows up.
+    fn apply_device_priority(&mut self) {
+        let Some(best) = self.priority_nodes.values().min().copied() else {
+            return;
+        };
+        let nodes: Vec<(u32, usize)> = self
+            .priority_nodes
+            .iter()
+            .map(|(node, priority)| (*node, *priority))
+            .collect();
+        for (node, priority) in nodes {
+            if priority == best && !self.ids_in_scope.contains(&node) {
+                info!(
+                    "Adding id [{}] as in scope due to it being the most preferred device present",
+                    node
+                );
+                self.mark_in_scope(node);
+            } else if priority != best && self.ids_in_scope.remove(&node) {
+                info!(
+                    "Removing id [{}] from scope due to a more preferred device being present",
+                    node
+                );
+                self.classified_at.remove(&node);
+            }
+        }
+    }
+
+    /// PipeWire reuses ids, so nothing known about the node may carry over to the next one.
+    pub fn remove_node(&mut self, id: &u32) {
+        self.ids_in_scope.remove(id);
+        self.ids_ignored.remove(id);
+        self.classified_at.remove(id);
+        self.registry.remove(id);
+        self.scoped_nodes.remove(id);
+        self.node_apps.remove(id);
+        self.source_devices.remove(id);
+        self.node_keys.remove(id);
+        self.node_props.remove(id);
+        self.node_roles.remove(id);
+        self.node_classes.remove(id);
+        self.live_nodes.remove(id);
+        self.named_nodes.remove(id);
+        self.node_names.remove(id);
+        if self.default_source_node == Some(*id) {
+            debug!("default source node [{}] removed", id);
+            self.default_source_node = None;
+        }
+        if self.priority_nodes.remove(id).is_some() {
+            debug!("node [{}] with device priority removed", id);
+            self.apply_device_priority();
+        }
+    }
+
+    /// Classifies the present nodes again against the new lists, dropping the links of nodes
+    /// that went out of scope. Links of nodes that came into scope only count once they are
+    /// linked again, like with device priorities.
+    pub fn set_device_lists(
+        &mut self,
+        devices_in_scope: HashSet<String>,
+        devices_ignored: HashSet<String>,
+    ) {
+        info!(
+            "Reloaded [{}] devices_in_scope and [{}] devices_ignored entries",
+            devices_in_scope.len(),
+            devices_ignored.len()
+        );
+        let was_in_scope = std::mem::take(&mut self.ids_in_scope);
+        self.devices_in_scope = devices_in_scope;
+        self.devices_ignored = devices_ignored;
+        self.matched_entries.clear();
+        self.classifications.clear();
+        let mut nodes: Vec<(u32, Vec<(String, String)>)> = self.node_props.drain().collect();
+        nodes.sort();
+        for (id, _) in &nodes {
+            self.remove_node(id);
+        }
+        self.ids_in_scope = scope_ids(&self.devices_in_scope);
+        self.ids_ignored = scope_ids(&self.devices_ignored);
+        for (id, props) in &nodes {
+            self.add_node(*id, &to_properties(props));
+        }
+        self.nodes_seen -= nodes.len();
+        for id in was_in_scope.difference(&self.ids_in_scope).copied().collect::<Vec<_>>() {
+            info!("Removing id [{}] from scope due to the reloaded lists", id);
+            self.drop_links_of(id);
+        }
+        self.update_level();
+        self.update_on_air();
+    }
+
+    /// Follows the default source, taking the previous one out of scope unless it is in scope
+    /// for other reasons. Like with device priorities, links that already exist keep counting
+    /// or not until they are linked again.
+    pub fn set_default_source(&mut self, name: Option<String>) {
+        if self.default_source == name {
+            return;
+        }
+        info!("Default source changed to [{}]", name.as_deref().unwrap_or("none"));
+        if let Some(previous) = self.default_source_node.take() {
+            info!(
+                "Removing id [{}] from scope due to it no longer being the default source",
+                previous
+            );
+            self.ids_in_scope.remove(&previous);
+            self.classified_at.remove(&previous);
+        }
+        let node = name.as_ref().and_then(|name| {
+            self.node_names
+                .iter()
+                .find(|(_, node_name)| *node_name == name)
+                .map(|(id, _)| *id)
+        });
+        self.default_source = name;
+        if let Some(node) = node {
+            self.scope_default_source_node(node);
+        }
+    }
+
+    fn scope_default_source_node(&mut self, id: u32) {
+        if self.ids_in_scope.contains(&id) {
+            return;
+        }
+        info!("Adding id [{}] as in scope due to it being the default source", id);
+        self.mark_in_scope(id);
+        self.default_source_node = Some(id);
+    }
+
+    fn mark_in_scope(&mut self, id: u32) {
+        if self.ids_in_scope.insert(id) {
+            self.classified_at.insert(id, Instant::now());
+        }
+    }
+
+    pub fn resolve_node_id(&self, id: &u32) -> &str {
+        self.registry
+            .get(id)
+            .unwrap_or_else(|| self.registry.get(&u32::MAX).unwrap())
+    }
+
+    /// Forgets the object with the given id, whatever its type.
+    pub fn remove_global(&mut self, id: &u32) {
+        self.remove_node(id);
+        self.device_names.remove(id);
+        self.port_formats.remove(id);
+        if let Some(on_air) = self.remove_link_if_present(id) {
+            info!("On Air: [{:?}]", on_air);
+        }
+    }
+
+    /// Checks and removes under the same lock, returns the resulting on air state if the link
+    /// was in scope and `None` otherwise.
+    pub fn remove_link_if_present(&mut self, id: &u32) -> Option<bool> {
+        if self.inactive_links.remove(id).is_some() {
+            debug!("Inactive in scope link [{}] removed.", id);
+            return None;
+        }
+        let link = self.active_links.remove(id)?;
+        info!("In scope link [{}] removed.", id);
+        self.update_level();
+        self.record_link_event(*id, link, LinkChange::Removed);
+        self.update_on_air();
+        Some(self.check_if_on_air())
+    }
+
+    fn transition(&self) -> Transition {
+        let mut devices: Vec<String> = self
+            .active_links
+            .values()
+            .map(|link| self.resolve_node_id(&link.output_node).to_string())
+            .collect();
+        devices.sort();
+        devices.dedup();
+        let labels = devices.iter().map(|device| self.label_for(device)).collect();
+        let mut apps: Vec<String> = self
+            .active_links
+            .values()
+            .map(|link| self.resolve_node_id(&link.input_node).to_string())
+            .collect();
+        apps.sort();
+        apps.dedup();
+        let mut scopes: Vec<usize> = self
+            .active_links
+            .values()
+            .flat_map(|link| self.matching_scopes(link.output_node, link.input_node))
+            .collect();
+        scopes.sort();
+        scopes.dedup();
+        Transition {
+            at: SystemTime::now(),
+            devices,
+            labels,
+            apps,
+            scopes: scopes
+                .into_iter()
+                .filter_map(|index| self.scopes[index].name.clone())
+                .collect(),
+        }
+    }
+
+    /// The configured label of a device, or its name if it has none.
+    fn label_for(&self, device: &str) -> String {
+        self.labels
+            .iter()
+            .find(|(pattern, _)| pattern::matches(pattern, device))
+            .map_or_else(|| device.to_string(), |(_, label)| label.clone())
+    }
+
+    fn run_on_air_hook(&mut self) {
+        let transition = self.transition();
+        self.reported_devices = transition.devices.clone();
+        self.reported_scopes = transition.scopes.clone();
+        let started = Instant::now();
+        let result = self.actor.go_on_air(&transition);
+        self.record_hook_duration("on air", started.elapsed());
+        let _ = self.observers.go_on_air(&transition);
+        if let Err(e) = result {
+            warn!("on air hook failed: {}", e);
+            self.counters.hook_failures += 1;
+        }
+    }
+
+    /// Tells the actors about devices linked or unlinked while staying on air. Devices going
+    /// away entirely, e.g. while smoothing keeps the tally on, aren't reported.
+    fn run_devices_hook(&mut self) {
+        let transition = self.transition();
+        if transition.devices.is_empty() || transition.devices == self.reported_devices {
+            return;
+        }
+        debug!(
+            "linked devices changed from [{}] to [{}]",
+            self.reported_devices.join(", "),
+            transition.devices.join(", ")
+        );
+        self.reported_devices = transition.devices.clone();
+        if let Err(e) = self.actor.on_devices_change(&transition) {
+            warn!("devices hook failed: {}", e);
+            self.counters.hook_failures += 1;
+        }
+    }
+
+    fn run_off_air_hook(&mut self) {
+        let mut transition = self.transition();
+        if transition.scopes.is_empty() {
+            transition.scopes = std::mem::take(&mut self.reported_scopes);
+        }
+        let started = Instant::now();
+        let result = self.actor.go_off_air(&transition);
+        self.record_hook_duration("off air", started.elapsed());
+        let _ = self.observers.go_off_air(&transition);
+        if let Err(e) = result {
+            warn!("off air hook failed: {}", e);
+            self.counters.hook_failures += 1;
+        }
+    }
+
+    fn record_hook_duration(&mut self, hook: &str, took: Duration) {
+        debug!("{} hook took [{:?}]", hook, took);
+        self.hook_timings.record(took);
+    }
+
+    /// Runs the warning hooks once the on air period reaches the threshold, and with `repeat`
+    /// every time it reaches another multiple of it.
+    fn check_on_air_warning(&mut self) {
+        let (Some(warning), Some(since)) = (self.on_air_warning, self.on_air_since) else {
+            return;
+        };
+        if warning.after.is_zero() || (self.warnings_sent > 0 && !warning.repeat) {
+            return;
+        }
+        let on_air_for = since.elapsed();
+        if on_air_for < warning.after * (self.warnings_sent + 1) {
+            return;
+        }
+        self.warnings_sent += 1;
+        info!("On air for [{}]", format_whole_seconds(on_air_for));
+        if self.hooks_held() {
+            info!("paused or outside active hours, not running warning hook");
+        } else if let Err(e) = self.actor.go_warning(&self.transition(), on_air_for) {
+            warn!("warning hook failed: {}", e);
+            self.counters.hook_failures += 1;
+        }
+    }
+
+    /// Runs the fan out alarm hooks once for every device whose number of active links left
+    /// the range configured for it, and again only after it was back in range. Devices without
+    /// links are only checked while on air, an unused device is expected to have none.
+    fn check_fan_out(&mut self) {
+        if self.fan_out.is_empty() || !self.initialized {
+            return;
+        }
+        let mut links: BTreeMap<String, Vec<String>> = self
+            .ids_in_scope
+            .iter()
+            .chain(self.scoped_nodes.keys())
+            .filter(|id| self.on_air && self.registry.contains_key(id))
+            .map(|id| (self.resolve_node_id(id).to_string(), Vec::new()))
+            .collect();
+        for link in self.active_links.values() {
+            links
+                .entry(self.resolve_node_id(&link.output_node).to_string())
+                .or_default()
+                .push(self.resolve_node_id(&link.input_node).to_string());
+        }
+        let mut alarms = Vec::new();
+        for (device, mut apps) in links {
+            let Some((_, expected)) = self
+                .fan_out
+                .iter()
+                .find(|(pattern, _)| pattern::matches(pattern, &device))
+            else {
+                continue;
+            };
+            if !expected.contains(apps.len()) {
+                apps.sort();
+                alarms.push(FanOutAlarm {
+                    label: self.label_for(&device),
+                    device,
+                    apps,
+                    expected: *expected,
+                });
+            }
+        }
+        let previous = std::mem::take(&mut self.fan_out_alarms);
+        for alarm in alarms {
+            self.fan_out_alarms.insert(alarm.device.clone());
+            if previous.contains(&alarm.device) {
+                continue;
+            }
+            warn!(
+                "[{}] has [{}] active links, expected {}",
+                alarm.device,
+                alarm.apps.len(),
+                alarm.expected
+            );
+            if self.hooks_held() {
+                info!("paused or outside active hours, not running fan out alarm hook");
+            } else if let Err(e) = self.actor.go_fan_out_alarm(&alarm) {
+                warn!("fan out alarm hook failed: {}", e);
+                self.counters.hook_failures += 1;
+            }
+        }
+        for device in previous.difference(&self.fan_out_alarms) {
+            info!("[{}] has the expected number of links again", device);
+        }
+    }
+
+    fn run_level_hook(&mut self, level: usize) {
+        if let Err(e) = self.actor.on_level_change(level) {
+            warn!("level hook failed: {}", e);
+            self.counters.hook_failures += 1;
+        }
+    }
+
+    /// Whether the actors are in their on air state, which is the opposite of the real state
+    /// if the tally is inverted.
+    fn indicator_on(&self) -> bool {
+        self.on_air != self.invert
+    }
+
+    /// Reports graphs that have no nodes at all, e.g. when connected to the wrong PipeWire
+    /// instance or before the audio stack is ready, separately from nodes not matching the
+    /// scope. Returns whether the graph was empty.
+    pub fn check_initial_nodes(&self) -> bool {
+        if self.nodes_seen == 0 {
+            warn!(
+                "Connected to PipeWire, but it doesn't announce any nodes, is the audio stack \
+                 running and the right remote configured?"
+            );
+            true
+        } else {
+            if self.ids_in_scope.is_empty()
+                && self.scoped_nodes.is_empty()
+                && !self.scope_default_source
+            {
+                warn!(
+                    "None of the [{}] nodes PipeWire announced are in scope, check devices_in_scope",
+                    self.nodes_seen
+                );
+            }
+            let unmatched = self.unmatched_entries();
+            if !unmatched.is_empty() {
+                info!("Entries of devices_in_scope without a match so far: {:?}", unmatched);
+            }
+            false
+        }
+    }
+
+    /// Called once PipeWire has answered the sync sent after connecting. Only the first call per
+    /// connection has an effect, returns whether the graph was empty.
+    pub fn finish_enumeration(&mut self) -> bool {
+        if std::mem::replace(&mut self.initialized, true) {
+            return false;
+        }
+        let mut in_scope: Vec<u32> = self.ids_in_scope.iter().copied().collect();
+        in_scope.sort_unstable();
+        info!(
+            "Initial enumeration complete, [{}] nodes seen, in scope {:?}",
+            self.nodes_seen, in_scope
+        );
+        self.check_initial_nodes()
+    }
+
+    /// An inverted tally starts out lit, as nothing is on air before the first link shows up.
+    /// Only the first call has an effect, later connections don't run hooks again.
+    pub fn startup(&mut self) {
+        if std::mem::replace(&mut self.started, true) {
+            return;
+        }
+        if self.indicator_on() && !self.hooks_held() {
+            info!("tally is inverted, running on air hook while off air");
+            self.run_on_air_hook();
+        }
+    }
+
+    /// While paused the on air state is still tracked, but hooks don't run. Pausing while on air
+    /// runs the off air hook and resuming while on air runs the on air hook, so external
+    /// indicators don't get stuck.
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused == paused {
+            return;
+        }
+        info!("{} hooks", if paused { "Pausing" } else { "Resuming" });
+        let was_held = self.hooks_held();
+        self.paused = paused;
+        self.sync_held_hooks(was_held);
+    }
+
+    /// Hooks only run while not paused and within the active hours.
+    fn hooks_held(&self) -> bool {
+        self.paused || self.outside_hours
+    }
+
+    /// Runs the hooks for the current state when hooks were just held back or released.
+    fn sync_held_hooks(&mut self, was_held: bool) {
+        let held = self.hooks_held();
+        if held == was_held {
+            return;
+        }
+        if self.indicator_on() {
+            if held {
+                self.run_off_air_hook();
+            } else {
+                self.run_on_air_hook();
+            }
+        }
+        if self.level > 0 {
+            self.run_level_hook(if held { 0 } else { self.level });
+        }
+    }
+
+    /// Entering or leaving the active hours behaves like resuming or pausing.
+    fn check_active_hours(&mut self) {
+        if self.active_hours.is_empty() {
+            return;
+        }
+        let outside = !hours::is_active(&self.active_hours, LocalTime::now());
+        if outside == self.outside_hours {
+            return;
+        }
+        info!(
+            "{} active hours, {} hooks",
+            if outside { "Left" } else { "Entered" },
+            if outside { "holding back" } else { "running" }
+        );
+        let was_held = self.hooks_held();
+        self.outside_hours = outside;
+        self.sync_held_hooks(was_held);
+    }
+
+    /// Like a link, forcing is subject to `on_air_delay` and the dwell times.
+    pub fn set_forced(&mut self, forced: bool) {
+        if self.forced_on_air == forced {
+            return;
+        }
+        self.forced_on_air = forced;
+        // the last link change didn't cause the transition following this
+        self.last_link_event = None;
+        self.update_on_air();
+    }
+
+    /// Manual override for when the actors got out of sync: goes off air and runs the hooks
+    /// for that, even if already off air or paused. The links are only evaluated again with
+    /// the next event, ticks don't undo the reset.
+    pub fn reset(&mut self) {
+        warn!("Manual reset, going off air regardless of the current links");
+        self.pending_on_air_since = None;
+        self.coalesce_until = None;
+        self.activity = 0.0;
+        // no link change caused this
+        self.last_link_event = None;
+        self.record_transition(false);
+        self.reset_held = true;
+        if self.indicator_on() {
+            self.run_on_air_hook();
+        } else {
+            self.run_off_air_hook();
+        }
+    }
+
+    /// Tells actors that just replaced others about the current state: while the indicator is
+    /// on they get the on air hook, and the level hook if apps are linked. Off air nothing is
+    /// run, as new actors are expected to start out off. Observers aren't called, for them
+    /// nothing changed.
+    pub fn replay(&mut self) {
+        if self.hooks_held() {
+            return;
+        }
+        if self.indicator_on() {
+            info!("running on air hook for the replaced actors");
+            let transition = self.transition();
+            if let Err(e) = self.actor.go_on_air(&transition) {
+                warn!("on air hook failed: {}", e);
+                self.counters.hook_failures += 1;
+            }
+        }
+        if self.level > 0 {
+            self.run_level_hook(self.level);
+        }
+    }
+
+    /// Only called when exiting normally, after a crash the indicator stays as it was.
+    pub fn shutdown(&mut self) {
+        if !self.run_off_air_on_shutdown {
+            info!("Leaving the indicator as it is, run_off_air_on_shutdown is disabled");
+        } else if !self.hooks_held() {
+            if self.indicator_on() {
+                info!("running off air hook before exiting");
+                self.run_off_air_hook();
+            }
+            if self.level > 0 {
+                self.run_level_hook(0);
+            }
+        }
+        info!("Dropped events: {}", self.counters);
+        info!("Hooks: {}", self.hook_timings);
+        if self.session_summary {
+            let mut sessions = self.sessions.clone();
+            if let Some(since) = self.on_air_since {
+                // still on air, the hooks may just have switched the indicator off
+                sessions.end(since.elapsed());
+            }
+            info!("Sessions: {}", sessions);
+        }
+        let unmatched = self.unmatched_entries();
+        if !unmatched.is_empty() {
+            warn!("Entries of devices_in_scope that never matched: {:?}", unmatched);
+        }
+        if let Some(reason) = &self.last_transition {
+            let ago = reason.at.elapsed().unwrap_or_default();
+            info!(
+                "Last transition to on air [{}] was [{:?}] ago, caused by {}",
+                reason.on_air, ago, reason
+            );
+        }
+        if let Some(average) = self.link_latency.average() {
+            info!(
+                "Average time from device appearing to its first link: [{:?}] over [{}] devices",
+                average, self.link_latency.count
+            );
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            on_air: self.on_air,
+            paused: self.paused,
+            apps: self.level,
+            devices: self.transition().devices,
+            links: self.link_status(),
+            unmatched: self.unmatched_entries(),
+            history: self.history.iter().cloned().collect(),
+            last_transition: self.last_transition.clone(),
+            transitions: self.transitions,
+            hook_timings: self.hook_timings,
+            counters: self.counters,
+            link_latency: self.link_latency,
+        }
+    }
+
+    fn link_status(&self) -> Vec<LinkStatus> {
+        let mut links: Vec<LinkStatus> = self
+            .active_links
+            .iter()
+            .map(|(id, link)| LinkStatus {
+                id: *id,
+                device: self.resolve_node_id(&link.output_node).to_string(),
+                target: self.resolve_node_id(&link.input_node).to_string(),
+                format: link
+                    .output_port
+                    .and_then(|port| self.port_formats.get(&port))
+                    .cloned(),
+                direction: link.direction,
+            })
+            .collect();
+        links.sort_by_key(|link| link.id);
+        links
+    }
+
+    pub fn check_if_on_air(&self) -> bool {
+        self.on_air
+    }
+}
+
+fn get_input_node(props: &impl Props) -> Result<&str, Error> {
+    props.get("link.input.node").context(NoInputNodeSnafu {
+        props: format!("{:?}", props),
+    })
+}
+
+fn get_output_node(props: &impl Props) -> Result<&str, Error> {
+    props.get("link.output.node").context(NoOutputNodeSnafu {
+        props: format!("{:?}", props),
+    })
+}
+
+fn get_link_nodes(props: &impl Props) -> Result<(u32, u32), Error> {
+    let output_node = get_output_node(props)?;
+    let input_node = get_input_node(props)?;
+    Ok((
+        parse_node_id("link.output.node", output_node)?,
+        parse_node_id("link.input.node", input_node)?,
+    ))
+}
+
+/// Parses the value of a property holding an id. Falls back to parsing the trimmed value
+/// without quotes, because not all PipeWire versions format ids as plain integers.
+pub fn parse_node_id(key: &'static str, value: &str) -> Result<u32, Error> {
+    u32::parse_value(value)
+        .or_else(|| value.trim().trim_matches('"').parse().ok())
+        .context(InvalidNodeIdSnafu { key, value })
+}
+
+/// Metadata key of the source apps are connected to unless they ask for a specific one.
+const DEFAULT_SOURCE_KEY: &str = "default.audio.source";
+
+/// Extracts the node name from a default node value like `{"name":"alsa_input.usb-..."}`.
+pub fn default_source_name(value: &str) -> Option<String> {
+    let (_, rest) = value.split_once("\"name\"")?;
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start().strip_prefix('"')?;
+    let mut name = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(name),
+            '\\' => name.push(chars.next()?),
+            c => name.push(c),
+        }
+    }
+    None
+}
+
+/// Streams that are capturing either say so with `stream.is-live` or report the latency they
+/// run with in `node.latency`.
+fn is_live_stream(props: &impl Props) -> bool {
+    match props.get(&keys::STREAM_IS_LIVE) {
+        Some(live) => live == "true",
+        None => props.get(&keys::NODE_LATENCY).is_some(),
+    }
+}
+
+/// The device a capture node belongs to, `None` for playback and device-less nodes.
+fn get_source_device(props: &impl Props) -> Option<u32> {
+    let media_class = props.get(&keys::MEDIA_CLASS)?;
+    if !media_class.contains("Source") {
+        return None;
+    }
+    props.get(&keys::DEVICE_ID).and_then(u32::parse_value)
+}
+
+/// Real capture devices, as opposed to virtual sources and monitors of outputs. Cameras only
+/// count with `include_video`.
+fn is_capture_device(props: &impl Props, include_video: bool) -> bool {
+    let media_class = props.get(&keys::MEDIA_CLASS);
+    (media_class == Some("Audio/Source") || (include_video && media_class == Some("Video/Source")))
+        && !is_monitor(props)
+}
+
+/// Nodes that produce audio or video, like microphones and cameras, as opposed to sinks. Nodes
+/// that don't announce a media class are given the benefit of the doubt.
+pub fn is_source_class(props: &impl Props) -> bool {
+    props
+        .get(&keys::MEDIA_CLASS)
+        .is_none_or(|class| class.contains("Source") || class.contains("Duplex"))
+}
+
+/// Monitors of outputs, which record what is played rather than a microphone.
+pub fn is_monitor(props: &impl Props) -> bool {
+    get_all_names(props)
+        .iter()
+        .any(|name| name.ends_with(".monitor"))
+}
+
+/// Many nodes, e.g. ones bridged from ALSA, don't have an `application.name`, so the process
+/// binary and the node name are tried as well.
+fn get_app_name(props: &impl Props) -> Option<&str> {
+    [*keys::APP_NAME, *keys::APP_PROCESS_BINARY, *keys::NODE_NAME]
+        .into_iter()
+        .find_map(|key| props.get(key))
+}
+
+/// Node ids given as `id:<n>` entries, invalid ones are skipped with a warning.
+fn owned_props(props: &impl Props) -> Vec<(String, String)> {
+    props
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn to_properties(props: &[(String, String)]) -> Properties {
+    let mut dict = Properties::new();
+    for (key, value) in props {
+        dict.insert(key.as_str(), value.as_str());
+    }
+    dict
+}
+
+fn scope_ids(entries: &HashSet<String>) -> HashSet<u32> {
+    entries
+        .iter()
+        .filter_map(|entry| entry.strip_prefix(ID_SCOPE_PREFIX))
+        .filter_map(|id| match id.trim().parse() {
+            Ok(id) => {
+                info!("Using node id [{}] from the config without matching names", id);
+                Some(id)
+            }
+            Err(_) => {
+                warn!("Skipping [{}{}], it is not a node id", ID_SCOPE_PREFIX, id);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Identifies a node across PipeWire restarts, which ids and `object.serial` don't: the serial
+/// and name of its device if it has them and its `node.name`.
+fn classification_key(props: &impl Props) -> Option<String> {
+    props.get(&keys::NODE_NAME)?;
+    let parts: Vec<&str> = [&keys::DEVICE_SERIAL, &keys::DEVICE_NAME, &keys::NODE_NAME]
+        .into_iter()
+        .filter_map(|key| props.get(key))
+        .collect();
+    Some(parts.join("/"))
+}
+
+/// Names `device:` scope entries are matched against.
+pub fn get_device_names(props: &impl Props) -> Vec<String> {
+    [
+        *keys::DEVICE_DESCRIPTION,
+        *keys::DEVICE_NICK,
+        *keys::DEVICE_NAME,
+        "api.alsa.card.name",
+    ]
+    .into_iter()
+    .filter_map(|key| props.get(key))
+    .map(str::to_string)
+    .collect()
+}
+
+pub fn get_all_names(props: &impl Props) -> Vec<&str> {
+    [&keys::NODE_DESCRIPTION, &keys::NODE_NICK, &keys::NODE_NAME]
+        .into_iter()
+        .filter_map(|prop_name| props.get(prop_name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Records the hooks that ran, and fails them all if `fail` is set. Levels are recorded on
+    /// their own, as they are reported with every link change.
+    #[derive(Clone, Default)]
+    struct MockActor {
+        calls: Arc<Mutex<Vec<String>>>,
+        levels: Arc<Mutex<Vec<usize>>>,
+        fail: bool,
+    }
+
+    impl MockActor {
+        fn record(&self, call: &str) -> Result<(), HookError> {
+            self.calls.lock().unwrap().push(call.to_string());
+            if self.fail {
+                return Err(format!("{} failed", call).into());
+            }
+            Ok(())
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl OnAirActor for MockActor {
+        fn go_on_air(&self, _transition: &Transition) -> Result<(), HookError> {
+            self.record("on")
+        }
+
+        fn go_off_air(&self, _transition: &Transition) -> Result<(), HookError> {
+            self.record("off")
+        }
+
+        fn on_level_change(&self, count: usize) -> Result<(), HookError> {
+            self.levels.lock().unwrap().push(count);
+            Ok(())
+        }
+    }
+
+    fn test_config() -> Config {
+        Config {
+            devices_in_scope: HashSet::from(["Mic".to_string()]),
+            ..Config::default()
+        }
+    }
+
+    /// A state with the in scope source `Mic` as node 1 and the app `App` as node 2.
+    fn state_with(config: &Config, actor: &MockActor) -> State<MockActor> {
+        pipewire::init();
+        let mut state = State::new(config, actor.clone());
+        add_node(&mut state, 1, "Mic", "Audio/Source");
+        add_node(&mut state, 2, "App", "Stream/Input/Audio");
+        state
+    }
+
+    fn add_node<T: OnAirActor>(state: &mut State<T>, id: u32, name: &str, media_class: &str) {
+        state.add_node(
+            id,
+            &properties! {
+                *keys::NODE_DESCRIPTION => name,
+                *keys::MEDIA_CLASS => media_class
+            },
+        );
+    }
+
+    fn add_link<T: OnAirActor>(state: &mut State<T>, id: u32, output_node: u32, input_node: u32) {
+        state.add_link(
+            &id,
+            &properties! {
+                *keys::LINK_OUTPUT_NODE => output_node.to_string(),
+                *keys::LINK_INPUT_NODE => input_node.to_string()
+            },
+        );
+    }
+
+    #[test]
+    fn hooks_only_run_on_edges() {
+        let actor = MockActor::default();
+        let mut state = state_with(&test_config(), &actor);
+        add_link(&mut state, 10, 1, 2);
+        add_link(&mut state, 11, 1, 2);
+        state.remove_link_if_present(&10);
+        add_link(&mut state, 12, 1, 2);
+        state.remove_link_if_present(&11);
+        add_link(&mut state, 10, 1, 2);
+        state.remove_link_if_present(&12);
+        assert_eq!(actor.calls(), ["on"]);
+        state.remove_link_if_present(&10);
+        assert_eq!(actor.calls(), ["on", "off"]);
+    }
+
+    #[test]
+    fn adding_a_link_again_is_idempotent() {
+        let actor = MockActor::default();
+        let mut state = state_with(&test_config(), &actor);
+        add_link(&mut state, 10, 1, 2);
+        add_link(&mut state, 10, 1, 2);
+        assert_eq!(actor.calls(), ["on"]);
+        state.remove_link_if_present(&10);
+        assert_eq!(actor.calls(), ["on", "off"]);
+        assert!(!state.check_if_on_air());
+    }
+
+    #[test]
+    fn reused_node_ids_are_classified_fresh() {
+        let actor = MockActor::default();
+        let mut state = state_with(&test_config(), &actor);
+        state.remove_node(&1);
+        add_node(&mut state, 1, "Webcam", "Audio/Source");
+        assert!(!state.ids_in_scope.contains(&1));
+        assert_eq!(state.resolve_node_id(&1), "Webcam");
+        add_link(&mut state, 10, 1, 2);
+        assert!(!state.check_if_on_air());
+        assert!(actor.calls().is_empty());
+    }
+
+    #[test]
+    fn reloaded_lists_classify_present_nodes_again() {
+        let actor = MockActor::default();
+        let mut state = state_with(&test_config(), &actor);
+        add_node(&mut state, 3, "Headset", "Audio/Source");
+        add_link(&mut state, 10, 1, 2);
+        assert!(state.check_if_on_air());
+        state.set_device_lists(HashSet::from(["Headset".to_string()]), HashSet::new());
+        assert!(!state.check_if_on_air());
+        assert!(!state.ids_in_scope.contains(&1));
+        assert!(state.ids_in_scope.contains(&3));
+        assert_eq!(state.nodes_seen, 3);
+        add_link(&mut state, 11, 3, 2);
+        assert!(state.check_if_on_air());
+        assert_eq!(actor.calls(), ["on", "off", "on"]);
+    }
+
+    #[test]
+    fn no_off_air_without_on_air() {
+        let actor = MockActor::default();
+        let mut state = state_with(&test_config(), &actor);
+        assert_eq!(state.remove_link_if_present(&10), None);
+        state.remove_node(&1);
+        assert!(actor.calls().is_empty());
+        assert!(!state.check_if_on_air());
+    }
+
+    #[test]
+    fn failing_hooks_still_change_the_state() {
+        let actor = MockActor {
+            fail: true,
+            ..MockActor::default()
+        };
+        let mut state = state_with(&test_config(), &actor);
+        add_link(&mut state, 10, 1, 2);
+        assert!(state.check_if_on_air());
+        assert_eq!(state.counters.hook_failures, 1);
+        add_link(&mut state, 11, 1, 2);
+        state.remove_link_if_present(&10);
+        state.remove_link_if_present(&11);
+        assert!(!state.check_if_on_air());
+        assert_eq!(actor.calls(), ["on", "off"]);
+        assert_eq!(state.counters.hook_failures, 2);
+    }
+
+    #[test]
+    fn classifications_are_reused_after_connecting_again() {
+        pipewire::init();
+        let config = Config {
+            devices_in_scope: HashSet::from(["device:Headset".to_string()]),
+            ..Config::default()
+        };
+        let mut state = State::new(&config, MockActor::default());
+        state.add_device(5, &properties! { *keys::DEVICE_DESCRIPTION => "Headset" });
+        let node = |device: &str| {
+            properties! {
+                *keys::NODE_NAME => "alsa_input.usb-headset.mono-fallback",
+                *keys::MEDIA_CLASS => "Audio/Source",
+                *keys::DEVICE_ID => device
+            }
+        };
+        state.add_node(7, &node("5"));
+        assert!(state.ids_in_scope.contains(&7));
+
+        // PipeWire restarted, the node shows up with new ids before its device
+        state.add_node(17, &node("15"));
+        assert!(state.ids_in_scope.contains(&17));
+        state.add_node(
+            18,
+            &properties! {
+                *keys::NODE_NAME => "alsa_input.usb-other.mono-fallback",
+                *keys::MEDIA_CLASS => "Audio/Source",
+                *keys::DEVICE_ID => "15"
+            },
+        );
+        assert!(!state.ids_in_scope.contains(&18));
+    }
+
+    #[test]
+    fn observers_are_called_on_every_transition() {
+        pipewire::init();
+        let mut watcher = RecordingWatcher::new(&test_config(), MockActor::default());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for name in ["first", "second"] {
+            let seen = seen.clone();
+            watcher.on_change(move |event| {
+                let devices = event.transition.devices.join(", ");
+                seen.lock().unwrap().push(format!("{} {} [{}]", name, event.on_air, devices));
+            });
+        }
+        let mut state = watcher.state.write().unwrap();
+        add_node(&mut state, 1, "Mic", "Audio/Source");
+        add_node(&mut state, 2, "App", "Stream/Input/Audio");
+        add_link(&mut state, 10, 1, 2);
+        state.remove_link_if_present(&10);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["first true [Mic]", "second true [Mic]", "first false []", "second false []"]
+        );
+    }
+
+    #[test]
+    fn combined_tally_is_on_air_while_any_device_is_linked() {
+        let config = Config {
+            devices_in_scope: HashSet::from(["Mic".to_string(), "Headset".to_string()]),
+            ..Config::default()
+        };
+        let actor = MockActor::default();
+        let mut state = state_with(&config, &actor);
+        add_node(&mut state, 3, "Headset", "Audio/Source");
+        add_link(&mut state, 10, 1, 2);
+        add_link(&mut state, 11, 3, 2);
+        state.remove_link_if_present(&10);
+        assert!(state.check_if_on_air());
+        assert_eq!(state.transition().devices, ["Headset"]);
+        state.remove_link_if_present(&11);
+        assert_eq!(actor.calls(), ["on", "off"]);
+    }
+
+    #[test]
+    fn link_directions() {
+        let classify = |output, input| LinkDirection::classify(Some(output), Some(input));
+        assert_eq!(classify("Audio/Source", "Stream/Input/Audio"), LinkDirection::Capture);
+        assert_eq!(classify("Stream/Output/Audio", "Audio/Sink"), LinkDirection::Playback);
+        assert_eq!(LinkDirection::classify(None, None), LinkDirection::Unknown);
+
+        // the player is in scope, but only feeds the headset
+        let config = Config {
+            devices_in_scope: HashSet::from(["Headset".to_string(), "Player".to_string()]),
+            require_source_class: false,
+            ..Config::default()
+        };
+        let actor = MockActor::default();
+        let mut state = state_with(&config, &actor);
+        add_node(&mut state, 3, "Headset", "Audio/Duplex");
+        add_node(&mut state, 4, "Player", "Stream/Output/Audio");
+        add_link(&mut state, 10, 4, 3);
+        assert!(actor.calls().is_empty());
+        add_link(&mut state, 11, 3, 2);
+        assert_eq!(actor.calls(), ["on"]);
+        let links = state.status().links;
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].direction, LinkDirection::Capture);
+        let json = state.status().to_json();
+        assert!(json.contains("\"target\": \"App\", \"format\": null, \"direction\": \"capture\""));
+    }
+
+    #[test]
+    fn playback_links_count_with_count_playback_links() {
+        let config = Config {
+            devices_in_scope: HashSet::from(["Player".to_string()]),
+            require_source_class: false,
+            count_playback_links: true,
+            ..Config::default()
+        };
+        let actor = MockActor::default();
+        let mut state = state_with(&config, &actor);
+        add_node(&mut state, 3, "Speaker", "Audio/Sink");
+        add_node(&mut state, 4, "Player", "Stream/Output/Audio");
+        add_link(&mut state, 10, 4, 3);
+        assert_eq!(actor.calls(), ["on"]);
+        assert_eq!(state.status().links[0].direction, LinkDirection::Playback);
+        assert!(state.status().to_json().contains("\"direction\": \"playback\""));
+    }
+
+    #[test]
+    fn notifiers_fall_back_to_the_next_command() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("onair-buddy-notifier-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let notifier = dir.join("notifier");
+        let log = dir.join("log");
+        fs::write(
+            &notifier,
+            format!("#!/bin/sh\nprintf '%s\\n' \"$*\" >> '{}'\n", log.display()),
+        )
+        .unwrap();
+        fs::set_permissions(&notifier, fs::Permissions::from_mode(0o700)).unwrap();
+        let transition = Transition {
+            at: SystemTime::now(),
+            devices: vec!["Mic".to_string()],
+            labels: vec!["Podcast Mic".to_string()],
+            apps: Vec::new(),
+            scopes: Vec::new(),
+        };
+
+        let config = NotificationConfig {
+            commands: vec![
+                "false".to_string(),
+                dir.join("missing").display().to_string(),
+                notifier.display().to_string(),
+            ],
+            ..NotificationConfig::default()
+        };
+        let actor = DebugActor::new(config, &[]);
+        actor.go_on_air(&transition).unwrap();
+        actor.go_off_air(&transition).unwrap();
+        assert_eq!(*actor.working.lock().unwrap(), Some(2));
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            "Going on air! (Podcast Mic)\nGoing off air!\n"
+        );
+
+        let config = NotificationConfig {
+            commands: vec!["false".to_string()],
+            ..NotificationConfig::default()
+        };
+        let error = DebugActor::new(config, &[]).go_on_air(&transition).unwrap_err();
+        assert!(error.to_string().starts_with("no notifier worked [false: "), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn links_from_a_node_to_itself_are_skipped() {
+        let actor = MockActor::default();
+        let mut state = state_with(&test_config(), &actor);
+        assert!(!state.add_link(
+            &10,
+            &properties! {
+                *keys::LINK_OUTPUT_NODE => "1",
+                *keys::LINK_INPUT_NODE => "1"
+            },
+        ));
+        assert!(state.active_links.is_empty());
+        assert!(!state.check_if_on_air());
+        assert!(actor.calls().is_empty());
+    }
+
+    #[test]
+    fn node_ids_as_integers_and_strings() {
+        assert_eq!(parse_node_id("link.input.node", "42").unwrap(), 42);
+        assert_eq!(parse_node_id("link.input.node", "\"42\"").unwrap(), 42);
+        assert_eq!(parse_node_id("link.input.node", " 42\n").unwrap(), 42);
+        match parse_node_id("link.output.node", "port.7") {
+            Err(Error::InvalidNodeId { key, value }) => {
+                assert_eq!(key, "link.output.node");
+                assert_eq!(value, "port.7");
+            }
+            other => panic!("expected an invalid node id, got {:?}", other),
+        }
+
+        let actor = MockActor::default();
+        let mut state = state_with(&test_config(), &actor);
+        assert!(state.add_link(
+            &10,
+            &properties! {
+                *keys::LINK_OUTPUT_NODE => "\"1\"",
+                *keys::LINK_INPUT_NODE => "2"
+            },
+        ));
+        assert_eq!(actor.calls(), ["on"]);
+    }
+
+    #[test]
+    fn bluetooth_profile_switch_renames_node() {
+        let config = Config {
+            devices_in_scope: HashSet::from(["Headset".to_string()]),
+            ..Config::default()
+        };
+        let actor = MockActor::default();
+        let mut state = state_with(&config, &actor);
+        add_node(&mut state, 3, "Headset", "Audio/Source");
+        add_link(&mut state, 10, 3, 2);
+        assert_eq!(actor.calls(), ["on"]);
+
+        // still matching through the nick, e.g. when switching to a profile with a new description
+        state.update_node_info(
+            3,
+            &properties! {
+                *keys::NODE_DESCRIPTION => "Headset (HFP)",
+                *keys::NODE_NICK => "Headset"
+            },
+        );
+        assert!(state.ids_in_scope.contains(&3));
+        assert_eq!(actor.calls(), ["on"]);
+
+        state.update_node_info(3, &properties! { *keys::NODE_DESCRIPTION => "Headset (A2DP)" });
+        assert!(!state.ids_in_scope.contains(&3));
+        assert!(state.active_links.is_empty());
+        assert_eq!(actor.calls(), ["on", "off"]);
+
+        state.update_node_info(3, &properties! { *keys::NODE_DESCRIPTION => "Headset" });
+        assert!(state.ids_in_scope.contains(&3));
+        assert!(!state.check_if_on_air());
+        add_link(&mut state, 11, 3, 2);
+        assert_eq!(actor.calls(), ["on", "off", "on"]);
+    }
+
+    #[test]
+    fn video_sources_count_with_include_video() {
+        for include_video in [false, true] {
+            let config = Config {
+                devices_in_scope: HashSet::new(),
+                scope_all_sources: true,
+                include_video,
+                ..Config::default()
+            };
+            let actor = MockActor::default();
+            let mut state = state_with(&config, &actor);
+            add_node(&mut state, 5, "Webcam", "Video/Source");
+            add_node(&mut state, 6, "Meeting", "Stream/Input/Video");
+            add_link(&mut state, 10, 5, 6);
+            assert_eq!(state.check_if_on_air(), include_video);
+            state.remove_link_if_present(&10);
+            let expected: &[&str] = if include_video { &["on", "off"] } else { &[] };
+            assert_eq!(actor.calls(), expected);
+        }
+    }
+
+    #[test]
+    fn status_includes_counters_and_last_transition() {
+        let config = Config { history_size: 0, ..test_config() };
+        let actor = MockActor::default();
+        let mut state = state_with(&config, &actor);
+        assert!(state.status().to_json().contains("\"last_transition\": null"));
+        add_link(&mut state, 10, 1, 2);
+        let status = state.status();
+        assert!(status.history.is_empty());
+        assert!(status.last_transition.as_ref().is_some_and(|last| last.on_air));
+        let json = status.to_json();
+        assert!(json.contains("\"last_transition\": {\"at\": "));
+        assert!(json.contains("\"transitions\": 1,"));
+        let counters = r#"{"malformed_links": 0, "parse_errors": 0, "hook_failures": 0}"#;
+        assert!(json.contains(&format!("\"counters\": {counters}")));
+    }
+
+    #[test]
+    fn prometheus_exports_transitions_and_failures() {
+        let actor = MockActor {
+            fail: true,
+            ..MockActor::default()
+        };
+        let mut state = state_with(&test_config(), &actor);
+        add_link(&mut state, 10, 1, 2);
+        state.remove_link_if_present(&10);
+        let metrics = state.status().to_prometheus();
+        assert!(metrics.contains("\nonair_buddy_transitions_total 2\n"), "{metrics}");
+        assert!(metrics.contains("\nonair_buddy_hook_failures_total 2\n"), "{metrics}");
+        assert!(metrics.contains("\nonair_buddy_link_latency_devices_total 1\n"), "{metrics}");
+        assert!(metrics.contains("\nonair_buddy_link_latency_seconds_avg "), "{metrics}");
+    }
+
+    #[test]
+    fn replays_the_default_source_after_enumeration() {
+        let config = Config {
+            devices_in_scope: HashSet::new(),
+            scope_default_source: true,
+            ..Config::default()
+        };
+        let log = "# onair-buddy event log 2\n\
+                   0\tglobal\t40\tNode\tnode.name=mic\tmedia.class=Audio/Source\n\
+                   0\tglobal\t55\tNode\tnode.name=app\tmedia.class=Stream/Input/Audio\n\
+                   1\tenumerated\n\
+                   2\tdefault-source\tmic\n\
+                   3\tglobal\t100\tLink\tlink.output.node=40\tlink.input.node=55\n";
+        let actor = MockActor::default();
+        let mut watcher = RecordingWatcher::new(&config, actor.clone());
+        watcher.run_replay(crate::event_log::parse(log).unwrap());
+        assert_eq!(actor.calls(), ["on", "off"]);
+    }
+
+    #[test]
+    fn notifications_replace_each_other_by_default() {
+        let config: NotificationConfig = toml::from_str("").unwrap();
+        assert!(config.replace);
+        let config: NotificationConfig = toml::from_str("replace = false").unwrap();
+        assert!(!config.replace);
+    }
+
+    #[test]
+    fn names_in_scope_may_contain_wildcards() {
+        let config = Config {
+            devices_in_scope: HashSet::from(["Headset*".to_string()]),
+            ..Config::default()
+        };
+        let actor = MockActor::default();
+        let mut state = state_with(&config, &actor);
+        add_node(&mut state, 3, "Headset (HFP)", "Audio/Source");
+        add_link(&mut state, 10, 1, 2);
+        assert!(!state.check_if_on_air());
+        add_link(&mut state, 11, 3, 2);
+        assert!(state.check_if_on_air());
+        assert!(state.unmatched_entries().is_empty());
+
+        // renamed to something else it goes out of scope
+        state.update_node_info(
+            3,
+            &properties! {
+                *keys::NODE_DESCRIPTION => "Speaker",
+                *keys::MEDIA_CLASS => "Audio/Source"
+            },
+        );
+        assert!(!state.check_if_on_air());
+    }
+
+    #[test]
+    fn ticks_do_not_undo_a_reset() {
+        let config = Config {
+            min_link_age: Duration::from_millis(1),
+            ..test_config()
+        };
+        let actor = MockActor::default();
+        let mut state = state_with(&config, &actor);
+        add_link(&mut state, 10, 1, 2);
+        thread::sleep(Duration::from_millis(5));
+        state.tick();
+        assert!(state.check_if_on_air());
+
+        state.reset();
+        state.tick();
+        assert!(!state.check_if_on_air());
+        assert_eq!(state.transitions, 2);
+        assert!(!state.history.back().unwrap().on_air);
+
+        // the next event evaluates the links again
+        add_link(&mut state, 11, 1, 2);
+        assert!(state.check_if_on_air());
+        assert_eq!(actor.calls(), ["on", "off", "on"]);
+    }
+
+    #[test]
+    fn replaced_actors_are_told_about_the_current_state() {
+        let actor = MockActor::default();
+        let mut state = state_with(&test_config(), &actor);
+        state.replay();
+        assert!(actor.calls().is_empty());
+        add_link(&mut state, 10, 1, 2);
+        actor.levels.lock().unwrap().clear();
+        state.replay();
+        assert_eq!(actor.calls(), ["on", "on"]);
+        assert_eq!(*actor.levels.lock().unwrap(), [1]);
+    }
 }
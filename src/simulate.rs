@@ -0,0 +1,379 @@
+//! Scripted registry events for trying out the on air logic without real hardware.
+//!
+//! Every line of a script holds a delay relative to the previous event followed by the event:
+//!
+//! ```text
+//! # comments and empty lines are ignored
+//! +0s    node add "My Mic"
+//! +0s    node add "Some App" app=zoom
+//! +0s    node add 60 "Camera" class=Video/Source
+//! +2s    link add out="My Mic" in="Some App"
+//! +1s    link state out="My Mic" in="Some App" paused
+//! +500ms link remove out="My Mic" in="Some App"
+//! +1s    node remove "My Mic"
+//! ```
+//!
+//! Objects get the next free id unless one is given, like the camera's 60 above. Nodes can be
+//! referred to by name or id, the latest node added with a name wins, links by id or by the
+//! nodes they connect.
+//!
+//! `app=` sets the `application.name` of a node, `class=` its `media.class` and `role=` its
+//! `media.role`. Link states only matter if `require_active_links` is set, links then start out
+//! inactive.
+use snafu::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Failed to read simulation script [{}]", path.display()))]
+    ReadScript {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[snafu(display("Invalid event in line {}: {}", line_number, reason))]
+    InvalidEvent { line_number: usize, reason: String },
+}
+
+/// The nodes and links of the script so far, for assigning ids and resolving references.
+struct Objects {
+    /// Ids and names of the nodes, in the order they were added.
+    nodes: Vec<(u32, String)>,
+    /// Ids, output and input nodes of the links.
+    links: Vec<(u32, u32, u32)>,
+    next_id: u32,
+}
+
+impl Default for Objects {
+    fn default() -> Self {
+        Objects {
+            nodes: Vec::new(),
+            links: Vec::new(),
+            // 0 is PipeWire's core.
+            next_id: 1,
+        }
+    }
+}
+
+impl Objects {
+    /// The given id, if any, or the next free one.
+    fn claim(&mut self, id: Option<&str>) -> Result<u32, String> {
+        let id = match id {
+            Some(id) => parse_id(id)?,
+            None => self.next_id,
+        };
+        let in_use = self.nodes.iter().any(|(node, _)| *node == id)
+            || self.links.iter().any(|(link, _, _)| *link == id);
+        if in_use {
+            return Err(format!("id [{}] is already in use", id));
+        }
+        self.next_id = self.next_id.max(id.saturating_add(1));
+        Ok(id)
+    }
+
+    /// Nodes not added by the script can still be referred to by id.
+    fn node(&self, node: &str) -> Result<u32, String> {
+        match self.nodes.iter().rev().find(|(_, name)| name == node) {
+            Some((id, _)) => Ok(*id),
+            None => parse_id(node).map_err(|_| format!("unknown node [{}]", node)),
+        }
+    }
+
+    /// A link given by its id or as `out=<node> in=<node>`.
+    fn link(&self, link: &[&str]) -> Result<u32, String> {
+        match link {
+            [id] => parse_id(id),
+            [output, input] => {
+                let output = self.node(named_arg("out", output)?)?;
+                let input = self.node(named_arg("in", input)?)?;
+                self.links
+                    .iter()
+                    .find(|(_, out, to)| (*out, *to) == (output, input))
+                    .map(|(id, _, _)| *id)
+                    .ok_or_else(|| format!("no link from [{}] to [{}]", output, input))
+            }
+            _ => Err(format!(
+                "expected a link id or out= and in=, got [{}]",
+                link.join(" ")
+            )),
+        }
+    }
+
+    fn apply(&mut self, event: &SimulatedEvent) {
+        match event {
+            SimulatedEvent::NodeAdded { id, name, .. } => self.nodes.push((*id, name.clone())),
+            SimulatedEvent::NodeRemoved { id } => self.nodes.retain(|(node, _)| node != id),
+            SimulatedEvent::LinkAdded {
+                id,
+                output_node,
+                input_node,
+            } => self.links.push((*id, *output_node, *input_node)),
+            SimulatedEvent::LinkRemoved { id } => self.links.retain(|(link, _, _)| link != id),
+            SimulatedEvent::LinkStateChanged { .. } => {}
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SimulatedEvent {
+    NodeAdded {
+        id: u32,
+        name: String,
+        app: Option<String>,
+        media_class: Option<String>,
+        role: Option<String>,
+    },
+    NodeRemoved {
+        id: u32,
+    },
+    LinkAdded {
+        id: u32,
+        output_node: u32,
+        input_node: u32,
+    },
+    LinkStateChanged {
+        id: u32,
+        active: bool,
+    },
+    LinkRemoved {
+        id: u32,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TimedEvent {
+    pub delay: Duration,
+    pub event: SimulatedEvent,
+}
+
+pub fn load(path: &Path) -> Result<Vec<TimedEvent>, Error> {
+    let script = fs::read_to_string(path).context(ReadScriptSnafu { path })?;
+    parse(&script)
+}
+
+pub fn parse(script: &str) -> Result<Vec<TimedEvent>, Error> {
+    let mut objects = Objects::default();
+    let mut events = Vec::new();
+    for (index, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let event = parse_line(line, &mut objects).map_err(|reason| Error::InvalidEvent {
+            line_number: index + 1,
+            reason,
+        })?;
+        objects.apply(&event.event);
+        events.push(event);
+    }
+    Ok(events)
+}
+
+fn parse_line(line: &str, objects: &mut Objects) -> Result<TimedEvent, String> {
+    let tokens = tokenize(line)?;
+    let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    let (delay, event) = tokens
+        .split_first()
+        .ok_or_else(|| "missing delay".to_string())?;
+    let delay = parse_delay(delay)?;
+    let event = match event {
+        ["node", "add", rest @ ..] => {
+            let (id, name, args) = match rest {
+                [id, name, args @ ..] if id.parse::<u32>().is_ok() && !is_node_arg(name) => {
+                    (Some(*id), name, args)
+                }
+                [name, args @ ..] => (None, name, args),
+                [] => return Err("missing node name".to_string()),
+            };
+            let mut app = None;
+            let mut media_class = None;
+            let mut role = None;
+            for arg in args {
+                match arg.split_once('=') {
+                    Some(("app", value)) => app = Some(value.to_string()),
+                    Some(("class", value)) => media_class = Some(value.to_string()),
+                    Some(("role", value)) => role = Some(value.to_string()),
+                    _ => {
+                        return Err(format!(
+                            "expected app=, class= or role=<value>, got [{}]",
+                            arg
+                        ))
+                    }
+                }
+            }
+            SimulatedEvent::NodeAdded {
+                id: objects.claim(id)?,
+                name: name.to_string(),
+                app,
+                media_class,
+                role,
+            }
+        }
+        ["node", "remove", node] => SimulatedEvent::NodeRemoved {
+            id: objects.node(node)?,
+        },
+        ["link", "add", id @ .., output, input] if id.len() <= 1 => {
+            let output_node = objects.node(named_arg("out", output)?)?;
+            let input_node = objects.node(named_arg("in", input)?)?;
+            SimulatedEvent::LinkAdded {
+                id: objects.claim(id.first().copied())?,
+                output_node,
+                input_node,
+            }
+        }
+        ["link", "state", link @ .., link_state] => SimulatedEvent::LinkStateChanged {
+            id: objects.link(link)?,
+            active: match *link_state {
+                "active" => true,
+                "paused" | "init" => false,
+                _ => return Err(format!("unknown link state [{}]", link_state)),
+            },
+        },
+        ["link", "remove", link @ ..] => SimulatedEvent::LinkRemoved {
+            id: objects.link(link)?,
+        },
+        _ => return Err(format!("unknown event [{}]", event.join(" "))),
+    };
+    Ok(TimedEvent { delay, event })
+}
+
+fn parse_delay(delay: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid delay [{}], expected e.g. +2s or +500ms", delay);
+    let delay = delay.strip_prefix('+').ok_or_else(invalid)?;
+    if let Some(millis) = delay.strip_suffix("ms") {
+        millis
+            .parse()
+            .map(Duration::from_millis)
+            .map_err(|_| invalid())
+    } else if let Some(secs) = delay.strip_suffix('s') {
+        secs.parse()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .ok_or_else(invalid)
+    } else {
+        Err(invalid())
+    }
+}
+
+fn parse_id(id: &str) -> Result<u32, String> {
+    id.parse().map_err(|_| format!("invalid id [{}]", id))
+}
+
+fn is_node_arg(arg: &str) -> bool {
+    ["app=", "class=", "role="]
+        .iter()
+        .any(|prefix| arg.starts_with(prefix))
+}
+
+fn named_arg<'a>(name: &str, arg: &'a str) -> Result<&'a str, String> {
+    arg.strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix('='))
+        .ok_or_else(|| format!("expected {}=<value>, got [{}]", name, arg))
+}
+
+/// Splits on whitespace, keeping double quoted parts together, also within a token like
+/// `out="My Mic"`.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut token: Option<String> = None;
+    let mut chars = line.trim().chars();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            let token = token.get_or_insert_with(String::new);
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => token.push(c),
+                    None => return Err("unterminated quote".to_string()),
+                }
+            }
+        } else if c.is_whitespace() {
+            tokens.extend(token.take());
+        } else {
+            token.get_or_insert_with(String::new).push(c);
+        }
+    }
+    tokens.extend(token);
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(script: &str) -> Vec<SimulatedEvent> {
+        parse(script)
+            .unwrap()
+            .into_iter()
+            .map(|event| event.event)
+            .collect()
+    }
+
+    fn node(id: u32, name: &str) -> SimulatedEvent {
+        SimulatedEvent::NodeAdded {
+            id,
+            name: name.to_string(),
+            app: None,
+            media_class: None,
+            role: None,
+        }
+    }
+
+    #[test]
+    fn assigns_ids_and_resolves_names() {
+        let script = r#"
+            +0s node add "My Mic"
+            +0s node add 40 "Some App"
+            +2s link add out="My Mic" in="Some App"
+            +1s link state out="My Mic" in=40 paused
+            +5s link remove 41
+            +0s node add "My Mic"
+            +0s node remove "My Mic"
+        "#;
+        assert_eq!(
+            events(script),
+            [
+                node(1, "My Mic"),
+                node(40, "Some App"),
+                SimulatedEvent::LinkAdded {
+                    id: 41,
+                    output_node: 1,
+                    input_node: 40,
+                },
+                SimulatedEvent::LinkStateChanged {
+                    id: 41,
+                    active: false,
+                },
+                SimulatedEvent::LinkRemoved { id: 41 },
+                node(42, "My Mic"),
+                SimulatedEvent::NodeRemoved { id: 42 },
+            ]
+        );
+    }
+
+    #[test]
+    fn names_may_look_like_ids() {
+        assert_eq!(
+            events("+0s node add 7 app=zoom\n+0s node add 7 \"7\""),
+            [
+                SimulatedEvent::NodeAdded {
+                    id: 1,
+                    name: "7".to_string(),
+                    app: Some("zoom".to_string()),
+                    media_class: None,
+                    role: None,
+                },
+                node(7, "7"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_ids_in_use_and_unknown_references() {
+        assert!(parse("+0s node add 1 \"Mic\"\n+0s link add 1 out=1 in=2").is_err());
+        assert!(parse("+0s node remove \"Mic\"").is_err());
+        assert!(parse("+0s link remove out=1 in=2").is_err());
+        assert!(parse("+0s node add 1 \"Mic\"\n+0s node remove 1\n+0s node add 1 \"Mic\"").is_ok());
+    }
+}
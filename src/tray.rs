@@ -0,0 +1,131 @@
+use crate::recording_watcher::{StatusSource, WatcherCommand};
+use ksni::blocking::TrayMethods;
+use ksni::menu::{CheckmarkItem, StandardItem};
+use ksni::{Icon, MenuItem};
+use pipewire::channel::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const ICON_SIZE: i32 = 22;
+
+/// How often the tray picks up the watcher's state.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct OnAirTray {
+    on_air: bool,
+    paused: bool,
+    commands: Sender<WatcherCommand>,
+}
+
+impl OnAirTray {
+    fn send(&self, command: WatcherCommand) {
+        if self.commands.send(command).is_err() {
+            warn!("failed to send command from tray to watcher");
+        }
+    }
+}
+
+impl ksni::Tray for OnAirTray {
+    fn id(&self) -> String {
+        crate::APP_NAME.into()
+    }
+
+    fn title(&self) -> String {
+        match (self.on_air, self.paused) {
+            (true, false) => "On air",
+            (true, true) => "On air (paused)",
+            (false, false) => "Off air",
+            (false, true) => "Off air (paused)",
+        }
+        .into()
+    }
+
+    fn icon_pixmap(&self) -> Vec<Icon> {
+        let color = if self.on_air {
+            [0xff, 0xe0, 0x1b, 0x24]
+        } else {
+            [0xff, 0x88, 0x88, 0x88]
+        };
+        vec![dot(color)]
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        vec![
+            CheckmarkItem {
+                label: "Paused".into(),
+                checked: self.paused,
+                activate: Box::new(|tray: &mut Self| {
+                    tray.paused = !tray.paused;
+                    tray.send(if tray.paused {
+                        WatcherCommand::Pause
+                    } else {
+                        WatcherCommand::Resume
+                    });
+                }),
+                ..Default::default()
+            }
+            .into(),
+            MenuItem::Separator,
+            StandardItem {
+                label: "Quit".into(),
+                icon_name: "application-exit".into(),
+                activate: Box::new(|tray: &mut Self| tray.send(WatcherCommand::Quit)),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+/// Shows the on air state in a StatusNotifierItem tray icon. The state is polled from the
+/// watcher rather than passed through the actors, so the icon and the paused checkmark stay
+/// right while hooks are held back, inverted or paused through the control socket.
+pub fn spawn(
+    commands: Sender<WatcherCommand>,
+    status: Arc<dyn StatusSource>,
+) -> Result<(), ksni::Error> {
+    let handle = OnAirTray {
+        on_air: false,
+        paused: false,
+        commands,
+    }
+    .spawn()?;
+    thread::spawn(move || {
+        let mut shown = (false, false);
+        while !handle.is_closed() {
+            let current = status.status();
+            let state = (current.on_air, current.paused);
+            if state != shown {
+                handle.update(|tray| (tray.on_air, tray.paused) = state);
+                shown = state;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        debug!("tray service is no longer running");
+    });
+    Ok(())
+}
+
+/// Filled circle in the given ARGB color on a transparent background.
+fn dot(color: [u8; 4]) -> Icon {
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 / 2.0 - 2.0;
+    let mut data = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+            if distance <= radius {
+                data.extend_from_slice(&color);
+            } else {
+                data.extend_from_slice(&[0, 0, 0, 0]);
+            }
+        }
+    }
+    Icon {
+        width: ICON_SIZE,
+        height: ICON_SIZE,
+        data,
+    }
+}